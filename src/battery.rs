@@ -1,21 +1,155 @@
-use crate::prelude::*;
+use crate::block::Block;
+use crate::config::BlockConfig;
+use crate::error::{self, Error, Result};
+use crate::format::{Format, Values};
+use crate::markup::{Severity, Text};
+use crate::root::Root;
 use std::io::prelude::*;
-use std::str::FromStr;
-use std::{fs, io};
+use std::{fs, io, time};
+use glob::glob;
 
 const CHARGE: &str = "🗲";
 const DISCHARGE: &str = "🔋";
 const FULL: &str = "🔌";
 const UNKNOWN: &str = "???";
+static FORMAT: &str = "{icon} {percent}%[ ({remaining})]";
+static FORMAT_NO_BATTERY: &str = "{icon} {source}";
+static WARN_PERCENT: f64 = 25.0;
+static BAD_PERCENT: f64 = 5.0;
+static WINDOW: f64 = 60.0;  // seconds the rate is averaged over
+static POWER_SUPPLY: &str = "/sys/class/power_supply";
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 enum BatteryState {
     Discharge,
     Charge,
     Full,
+    // plugged in but not charging, eg. kept below a charge threshold
+    Idle,
     Unknown,
 }
 
+// One battery, with charges converted to energies
+struct Supply {
+    name: String,
+    state: BatteryState,
+    remaining: i32,  // in uWh
+    full_design: i32,  // in uWh
+    full_last: i32,  // in uWh
+    present_rate: i32,  // in uW, always non-negative
+    cycles: Option<u32>,
+    technology: Option<String>,
+    manufacturer: Option<String>,
+    model: Option<String>,
+}
+
+impl Supply {
+    fn percentage(&self, design: bool) -> Option<f64> {
+        percentage(self.remaining, if design { self.full_design } else { self.full_last })
+    }
+
+    // how much of its design capacity the battery still holds
+    fn health(&self) -> Option<f64> {
+        health(self.full_last, self.full_design)
+    }
+
+    // eg. "/sys/class/power_supply/BAT0: Li-poly, SMP 01AV430, 312 cycles, 88% health"
+    fn describe(&self, root: &Root) -> String {
+        let mut details = vec![];
+        details.extend(self.technology.clone());
+        let maker: Vec<&str> = self.manufacturer.iter().chain(self.model.iter()).map(|name| &name[..]).collect();
+        if !maker.is_empty() {
+            details.push(maker.join(" "));
+        }
+        details.extend(self.cycles.map(|cycles| format!("{} cycles", cycles)));
+        details.extend(self.health().map(|health| format!("{:0.0}% health", health)));
+        format!("{}: {}", root.path(&format!("{}/{}", POWER_SUPPLY, self.name)), details.join(", "))
+    }
+}
+
+// What can power the system instead: mains, or a USB charger
+struct Adapter {
+    kind: &'static str,
+    online: bool,
+    watts: Option<f64>,
+}
+
+impl Adapter {
+    // "on AC", "on USB-C 45W"
+    fn describe(&self) -> String {
+        match self.watts {
+            Some(watts) => format!("on {} {:0.0}W", self.kind, watts),
+            None => format!("on {}", self.kind),
+        }
+    }
+}
+
+struct Info {
+    state: BatteryState,
+    percentage_remaining: Option<f64>,
+    health: Option<f64>,
+    remaining: i32,  // in uWh
+    full: i32,  // in uWh, as last charged
+    present_rate: i32,  // in uW
+    seconds_remaining: i32,
+}
+
+// The rate of (dis)charge, averaged over the updates since the state last
+// changed
+struct Draw {
+    state: BatteryState,
+    rate: Option<f64>,  // in uW
+    remaining: i32,  // in uWh
+    at: time::Instant,
+}
+
+pub struct Battery {
+    root: Root,
+    format: Format,
+    warn: f64,
+    bad: f64,
+    // only this battery, eg. "BAT1", rather than all of them together
+    only: Option<String>,
+    // when there's an adapter but no battery, eg. on a desktop
+    format_no_battery: Format,
+    // how long ago rates still count, in seconds
+    window: f64,
+    // percentages of the design capacity, rather than of the last full charge
+    design: bool,
+    // watts drawn from the battery
+    power_warn: f64,
+    power_bad: f64,
+    batteries: Vec<Supply>,
+    adapters: Vec<Adapter>,
+    info: Option<Info>,
+    draw: Option<Draw>,
+}
+
+impl Battery {
+    pub fn new(config: &BlockConfig) -> Self {
+        Self {
+            root: config.root.clone(),
+            format: config.get_format("format", FORMAT),
+            warn: config.get_f64("warn", WARN_PERCENT),
+            bad: config.get_f64("bad", BAD_PERCENT),
+            only: if config.has("battery") { Some(config.get_str("battery", "").to_string()) } else { None },
+            format_no_battery: config.get_format("format_no_battery", FORMAT_NO_BATTERY),
+            window: config.get_f64("window", WINDOW).max(0.0),
+            power_warn: config.get_f64("power_warn", f64::INFINITY),
+            power_bad: config.get_f64("power_bad", f64::INFINITY),
+            design: match config.get_str("basis", "last_full") {
+                "design" => true,
+                "last_full" => false,
+                _ => config.invalid("basis", "\"design\" or \"last_full\"", false),
+            },
+            batteries: vec![],
+            adapters: vec![],
+            info: None,
+            draw: None,
+        }
+    }
+}
+
 fn match_consume(string: &mut &str, prefix: &str) -> bool {
     if string.starts_with(prefix) {
         *string = &string[prefix.len()..];
@@ -25,58 +159,106 @@ fn match_consume(string: &mut &str, prefix: &str) -> bool {
     }
 }
 
-pub fn status() -> Option<String> {
-    let fd = match fs::File::open("/sys/class/power_supply/BAT0/uevent") {
+fn percentage(remaining: i32, full: i32) -> Option<f64> {
+    if full <= 0 {
+        // We have no physical measurements and no estimates. Nothing
+        // much we can report, then.
+        return None;
+    }
+    // Some batteries report POWER_SUPPLY_CHARGE_NOW=<full_design> when fully
+    // charged, even though that’s plainly wrong. As the percentage is
+    // calculated based on the last full capacity, we clamp the value to 100%,
+    // as that makes more sense.
+    // See http://bugs.debian.org/785398
+    Some((100.0 * remaining as f64 / full as f64).min(100.0))
+}
+
+// Not clamped: a new battery may hold more than it was designed for
+fn health(full_last: i32, full_design: i32) -> Option<f64> {
+    if full_last <= 0 || full_design <= 0 {
+        return None;
+    }
+    Some(100.0 * full_last as f64 / full_design as f64)
+}
+
+// The power supply `name`, if it's a battery powering the system (not eg. a
+// wireless mouse's, which has SCOPE=Device)
+fn read_supply(root: &Root, name: &str) -> Result<Option<Supply>> {
+    let path = &root.path(&format!("{}/{}/uevent", POWER_SUPPLY, name));
+    let fd = match fs::File::open(path) {
         Ok(fd) => io::BufReader::new(fd),
         Err(_err) => {
-            return None;
+            return Ok(None);
         },
     };
+    let mut battery = false;
+    let mut device = false;
     let mut state = BatteryState::Unknown;
     let mut voltage = -1;
     let mut full_design = 0;  // in uAh
     let mut full_last = 0;  // in uAh
     let mut remaining = 0;  // in uAh
     let mut present_rate = 0;  // in uA, always non-negative
-    let mut seconds_remaining = 0;
     let mut watt_as_unit = false;
+    let mut cycles = None;
+    let mut technology = None;
+    let mut manufacturer = None;
+    let mut model = None;
+    let text = |value: &str| Some(value.trim().to_string()).filter(|value| !value.is_empty());
 
     for line in fd.lines() {
-        let line = line.expect("Failed to read battery line");
+        let line = line.map_err(error::io(path))?;
         let mut l = &line[..];
 
         if match_consume(&mut l, "POWER_SUPPLY_") {
-            if match_consume(&mut l, "STATUS=") {
+            if match_consume(&mut l, "TYPE=") {
+                battery = l == "Battery";
+            } else if match_consume(&mut l, "SCOPE=") {
+                device = l == "Device";
+            } else if match_consume(&mut l, "STATUS=") {
                 state = match l {
                     "Discharging" => BatteryState::Discharge,
                     "Charging" => BatteryState::Charge,
                     "Full" => BatteryState::Full,
+                    "Not charging" => BatteryState::Idle,
                     _ => BatteryState::Unknown,
                 };
             } else if match_consume(&mut l, "ENERGY_NOW=") {
                 watt_as_unit = true;
-                remaining = i32::from_str(l).unwrap();
+                remaining = error::parse(path, l)?;
             } else if match_consume(&mut l, "CHARGE_NOW=") {
                 watt_as_unit = false;
-                remaining = i32::from_str(l).unwrap();
+                remaining = error::parse(path, l)?;
             } else if match_consume(&mut l, "CURRENT_NOW=") {
-                present_rate = i32::from_str(l).unwrap().abs();
+                present_rate = error::parse::<i32>(path, l)?.abs();
             } else if match_consume(&mut l, "VOLTAGE_NOW=") {
-                voltage = i32::from_str(l).unwrap().abs();
+                voltage = error::parse::<i32>(path, l)?.abs();
 
             // on some systems POWER_SUPPLY_POWER_NOW does not exist, but actually
             // it is the same as POWER_SUPPLY_CURRENT_NOW but with μWh as
             // unit instead of μAh. We will calculate it as we need it
             // later.
             } else if match_consume(&mut l, "POWER_NOW=") {
-                present_rate = i32::from_str(l).unwrap().abs();
+                present_rate = error::parse::<i32>(path, l)?.abs();
             } else if match_consume(&mut l, "CHARGE_FULL_DESIGN=") || match_consume(&mut l, "ENERGY_FULL_DESIGN=") {
-                full_design = i32::from_str(l).unwrap();
+                full_design = error::parse(path, l)?;
             } else if match_consume(&mut l, "ENERGY_FULL=") || match_consume(&mut l, "CHARGE_FULL=") {
-                full_last = i32::from_str(l).unwrap();
+                full_last = error::parse(path, l)?;
+            } else if match_consume(&mut l, "CYCLE_COUNT=") {
+                // 0 when the battery doesn't count them
+                cycles = Some(error::parse::<u32>(path, l)?).filter(|&cycles| cycles > 0);
+            } else if match_consume(&mut l, "TECHNOLOGY=") {
+                technology = text(l);
+            } else if match_consume(&mut l, "MANUFACTURER=") {
+                manufacturer = text(l);
+            } else if match_consume(&mut l, "MODEL_NAME=") {
+                model = text(l);
             }
         }
     }
+    if !battery || device {
+        return Ok(None);
+    }
 
     // the difference between POWER_SUPPLY_ENERGY_NOW and
     // POWER_SUPPLY_CHARGE_NOW is the unit of measurement. The energy is
@@ -90,53 +272,387 @@ pub fn status() -> Option<String> {
         full_last = ((voltage / 1000.0) * (full_last as f64 / 1000.0)).floor() as i32;
     }
 
-    let last_full_capacity = true;
-    let full = if last_full_capacity { full_last } else { full_design };
-    if full < 0 {
-        // We have no physical measurements and no estimates. Nothing
-        // much we can report, then.
-        Some(BAD.to_string() + "No battery")
-    } else {
-        let mut percentage_remaining = 100.0 * remaining as f64 / full as f64;
-
-        // Some batteries report POWER_SUPPLY_CHARGE_NOW=<full_design> when fully
-        // charged, even though that’s plainly wrong. For people who chose to see
-        // the percentage calculated based on the last full capacity, we clamp the
-        // value to 100%, as that makes more sense.
-        // See http://bugs.debian.org/785398
-        if last_full_capacity && percentage_remaining > 100.0 {
-            percentage_remaining = 100.0;
+    Ok(Some(Supply { name: name.to_string(), state, remaining, full_design, full_last, present_rate, cycles, technology, manufacturer, model }))
+}
+
+// The power supply `name`, if it's a mains or USB adapter
+fn read_adapter(root: &Root, name: &str) -> Result<Option<Adapter>> {
+    let path = &root.path(&format!("{}/{}/uevent", POWER_SUPPLY, name));
+    let fd = match fs::File::open(path) {
+        Ok(fd) => io::BufReader::new(fd),
+        Err(_err) => {
+            return Ok(None);
+        },
+    };
+    let mut kind = None;
+    let mut online = false;
+    let mut voltage = 0;  // in uV
+    let mut current = 0;  // in uA, the most the charger offers
+    for line in fd.lines() {
+        let line = line.map_err(error::io(path))?;
+        let mut l = &line[..];
+
+        if match_consume(&mut l, "POWER_SUPPLY_") {
+            if match_consume(&mut l, "TYPE=") {
+                kind = match l {
+                    "Mains" => Some("AC"),
+                    "USB_C" | "USB_PD" | "USB_PD_DRP" => Some("USB-C"),
+                    "USB" => Some(kind.unwrap_or("USB")),
+                    _ => None,
+                };
+            } else if match_consume(&mut l, "USB_TYPE=") {
+                // the one in use is in brackets, eg. "C [PD] PD_PPS"
+                let selected = l.split(' ').find(|usb_type| usb_type.starts_with('[')).map(|usb_type| usb_type.trim_matches(&['[', ']'][..]));
+                if matches!(selected, Some("C" | "PD" | "PD_DRP" | "PD_PPS")) && kind != Some("AC") {
+                    kind = Some("USB-C");
+                }
+            } else if match_consume(&mut l, "ONLINE=") {
+                online = l == "1";
+            } else if match_consume(&mut l, "VOLTAGE_NOW=") {
+                voltage = error::parse::<i64>(path, l)?.abs();
+            } else if match_consume(&mut l, "CURRENT_MAX=") {
+                current = error::parse::<i64>(path, l)?.abs();
+            }
         }
+    }
+    let watts = if voltage > 0 && current > 0 { Some(voltage as f64 * current as f64 / 1e12) } else { None };
+    Ok(kind.map(|kind| Adapter { kind, online, watts }))
+}
+
+// Every battery, or just `only`, in name order, and every adapter
+fn read(root: &Root, only: Option<&str>) -> Result<(Vec<Supply>, Vec<Adapter>)> {
+    let mut batteries = vec![];
+    let mut adapters = vec![];
+    // the root may be any directory, eg. one with a "[" in its name
+    let pattern = format!("{}/*/uevent", glob::Pattern::escape(&root.path(POWER_SUPPLY)));
+    let entries = glob(&pattern).map_err(|err| Error::Parse(format!("{}: {}", pattern, err)))?;
+    for entry in entries {
+        let name = match entry.ok().as_ref().and_then(|path| path.parent()?.file_name()?.to_str().map(|name| name.to_string())) {
+            Some(name) => name,
+            None => continue,
+        };
+        if let Some(supply) = read_supply(root, &name)? {
+            if only.is_none_or(|only| only == name) {
+                batteries.push(supply);
+            }
+        } else if let Some(adapter) = read_adapter(root, &name)? {
+            adapters.push(adapter);
+        }
+    }
+    Ok((batteries, adapters))
+}
 
-        if present_rate > 0 && state != BatteryState::Full {
-            seconds_remaining = if state == BatteryState::Charge {
-                (3600.0 * (full - remaining) as f64 / present_rate as f64) as i32
-            } else if state == BatteryState::Discharge {
-                (3600.0 * remaining as f64 / present_rate as f64) as i32
-            } else {
-                0
-            };
+// What the system is running on: the adapter in use, preferring USB ones
+// (whose wattage is known) to AC, which some laptops report online along
+// with them.  None without any adapter to tell.
+fn source(adapters: &[Adapter]) -> Option<String> {
+    if adapters.is_empty() {
+        return None;
+    }
+    let online = adapters.iter().filter(|adapter| adapter.online);
+    Some(match online.clone().find(|adapter| adapter.kind != "AC").or_else(|| online.clone().next()) {
+        Some(adapter) => adapter.describe(),
+        None => "unplugged".to_string(),
+    })
+}
+
+// The batteries as one: their energy added up, drained (or filled) at their
+// combined rate
+fn combine(batteries: &[Supply], adapters: &[Adapter], design: bool) -> Option<Info> {
+    if batteries.is_empty() {
+        return None;
+    }
+    let plugged = adapters.iter().any(|adapter| adapter.online);
+    let state = if batteries.iter().any(|battery| battery.state == BatteryState::Discharge) {
+        BatteryState::Discharge
+    } else if batteries.iter().any(|battery| battery.state == BatteryState::Charge) {
+        BatteryState::Charge
+    } else if batteries.iter().all(|battery| battery.state == BatteryState::Full) {
+        BatteryState::Full
+    } else if batteries.iter().all(|battery| battery.state != BatteryState::Unknown) || plugged {
+        // some firmware says "Unknown" at a threshold
+        BatteryState::Idle
+    } else {
+        BatteryState::Unknown
+    };
+    let remaining: i32 = batteries.iter().map(|battery| battery.remaining).sum();
+    let full: i32 = batteries.iter().map(|battery| battery.full_last).sum();
+    let full_design: i32 = batteries.iter().map(|battery| battery.full_design).sum();
+    // the others are idle, eg. kept at a charge threshold
+    let present_rate: i32 = batteries.iter().filter(|battery| battery.state == state).map(|battery| battery.present_rate).sum();
+
+    let percentage_remaining = percentage(remaining, if design { full_design } else { full });
+    let health = health(full, full_design);
+    Some(Info { state, percentage_remaining, health, remaining, full, present_rate, seconds_remaining: 0 })
+}
+
+impl Battery {
+    // Work out the time remaining from the rate averaged over `window`, so
+    // that a burst of load doesn't make it jump from hours to minutes and
+    // back.  Without a rate from the battery, the change in energy since the
+    // last update stands in.
+    fn estimate(&mut self, now: time::Instant) {
+        let info = match &mut self.info {
+            Some(info) if info.state == BatteryState::Charge || info.state == BatteryState::Discharge => info,
+            _ => {
+                self.draw = None;
+                return;
+            },
+        };
+        let draw = match self.draw.take() {
+            // start over when it's plugged in or out
+            Some(draw) if draw.state == info.state => draw,
+            _ => Draw { state: info.state, rate: None, remaining: info.remaining, at: now },
+        };
+        let elapsed = (now - draw.at).as_secs_f64();
+        let sample = if info.present_rate > 0 {
+            Some(info.present_rate as f64)
+        } else if elapsed > 0.0 && info.remaining != draw.remaining {
+            Some(3600.0 * (info.remaining - draw.remaining).abs() as f64 / elapsed)
+        } else {
+            None
+        };
+        let rate = match (draw.rate, sample) {
+            (Some(rate), Some(sample)) => {
+                let weight = if self.window > 0.0 { 1.0 - (-elapsed / self.window).exp() } else { 1.0 };
+                Some(rate + weight * (sample - rate))
+            },
+            (rate, sample) => sample.or(rate),
+        };
+        info.seconds_remaining = match rate {
+            Some(rate) if rate > 0.0 && info.percentage_remaining.is_some() => match info.state {
+                BatteryState::Charge => (3600.0 * (info.full - info.remaining) as f64 / rate) as i32,
+                _ => (3600.0 * info.remaining as f64 / rate) as i32,
+            },
+            _ => 0,
+        };
+        self.draw = Some(Draw { state: info.state, rate, remaining: info.remaining, at: now });
+    }
+}
+
+impl Block for Battery {
+    fn update(&mut self) -> Result<()> {
+        (self.batteries, self.adapters) = read(&self.root, self.only.as_deref())?;
+        self.info = combine(&self.batteries, &self.adapters, self.design);
+        self.estimate(time::Instant::now());
+        Ok(())
+    }
+
+    // the rate before the suspend says nothing about the one after
+    fn resume(&mut self) {
+        self.draw = None;
+    }
+
+    fn describe(&self) -> Option<String> {
+        if self.batteries.is_empty() {
+            return Some("no battery".to_string());
         }
+        Some(self.batteries.iter().map(|battery| battery.describe(&self.root)).collect::<Vec<_>>().join("; "))
+    }
 
-        let mut ret = if state == BatteryState::Discharge && percentage_remaining < 25.0 {
-            WARN.to_string()
-        } else if state == BatteryState::Unknown || (state == BatteryState::Discharge && percentage_remaining < 5.0) {
-            BAD.to_string()
+    fn render(&self) -> Option<Text> {
+        let source = source(&self.adapters);
+        let info = match &self.info {
+            Some(info) => info,
+            None => {
+                let mut values = Values::new();
+                values.insert("icon", FULL.to_string());
+                values.insert("source", source?);
+                return Some(Text::normal(self.format_no_battery.render(&values)));
+            },
+        };
+        let state = &info.state;
+        let seconds_remaining = info.seconds_remaining;
+        let percentage_remaining = match info.percentage_remaining {
+            Some(percentage) => percentage,
+            None => { return Some(Text::new(Severity::Bad, "No battery")); },
+        };
+
+        let severity = if *state == BatteryState::Unknown || (*state == BatteryState::Discharge && percentage_remaining < self.bad) {
+            Severity::Bad
+        } else if *state == BatteryState::Discharge && percentage_remaining < self.warn {
+            Severity::Warn
         } else {
-            "".to_string()
+            Severity::Normal
         };
+        // eg. a runaway process draining it
+        let watts = info.present_rate as f64 / 1e6;
+        let severity = severity.max(if *state != BatteryState::Discharge {
+            Severity::Normal
+        } else if watts > self.power_bad {
+            Severity::Bad
+        } else if watts > self.power_warn {
+            Severity::Warn
+        } else {
+            Severity::Normal
+        });
+        let mut values = Values::new();
+        if (*state == BatteryState::Discharge || *state == BatteryState::Charge) && info.present_rate > 0 {
+            values.insert("power", format!("{:0.1}", watts));
+        }
         match state {
             BatteryState::Discharge | BatteryState::Charge | BatteryState::Unknown => {
-                ret += if state == BatteryState::Charge { CHARGE } else if state == BatteryState::Discharge { DISCHARGE } else { UNKNOWN };
-                ret += &format!(" {:0.0}% (", percentage_remaining);
-                if seconds_remaining > 3600 {
-                    ret += &format!("{}h{:02})", seconds_remaining / 3600, (seconds_remaining % 3600) / 60);
-                } else {
-                    ret += &format!("{}m{:02})", seconds_remaining / 60, seconds_remaining % 60);
+                values.insert("icon", if *state == BatteryState::Charge { CHARGE } else if *state == BatteryState::Discharge { DISCHARGE } else { UNKNOWN }.to_string());
+                values.insert("percent", format!("{:0.0}", percentage_remaining));
+                // none until there's a rate to go by
+                if seconds_remaining >= 3600 {
+                    values.insert("remaining", format!("{}h{:02}", seconds_remaining / 3600, (seconds_remaining % 3600) / 60));
+                } else if seconds_remaining > 0 {
+                    values.insert("remaining", format!("{}m{:02}", seconds_remaining / 60, seconds_remaining % 60));
                 }
             },
-            BatteryState::Full => { ret += FULL; ret += " 100%"; },
+            BatteryState::Full => {
+                values.insert("icon", FULL.to_string());
+                // short of 100 for a worn battery
+                values.insert("percent", if self.design { format!("{:0.0}", percentage_remaining) } else { "100".to_string() });
+            },
+            BatteryState::Idle => {
+                values.insert("icon", FULL.to_string());
+                values.insert("percent", format!("{:0.0}", percentage_remaining));
+            },
+        };
+        if let Some(source) = source {
+            values.insert("source", source);
+        }
+        if let Some(health) = info.health {
+            values.insert("health", format!("{:0.0}", health));
+        }
+        // each battery on its own
+        values.insert("batteries", self.batteries.iter().filter_map(|battery| battery.percentage(self.design).map(|percentage| format!("{:0.0}%", percentage))).collect::<Vec<_>>().join(" "));
+        values.insert("state", match state {
+            BatteryState::Discharge => "discharging",
+            BatteryState::Charge => "charging",
+            BatteryState::Full => "full",
+            BatteryState::Idle => "not charging",
+            BatteryState::Unknown => "unknown",
+        }.to_string());
+        Some(Text::new(severity, self.format.render(&values)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Battery, BatteryState};
+    use crate::block::Block;
+    use crate::markup::{Severity, Text};
+    use crate::root;
+    use std::time;
+
+    fn battery(fixture: &str, options: &str) -> Battery {
+        let config = root::config("battery", options, Some(fixture));
+        let mut battery = Battery::new(&config);
+        battery.update().unwrap();
+        battery
+    }
+
+    #[test]
+    fn energy() {
+        assert_eq!(battery("intel-discharging", "").render(), Some(Text::new(Severity::Normal, "🔋 54% (3h37)")));
+        assert_eq!(battery("intel-charging", "").render(), Some(Text::new(Severity::Normal, "🗲 80% (24m51)")));
+        assert_eq!(battery("intel-discharging", "warn = 60").render().unwrap().severity, Severity::Warn);
+    }
+
+    #[test]
+    fn no_rate() {
+        // no power_now or current_now, so no estimate
+        assert_eq!(battery("no-rate", "").render(), Some(Text::new(Severity::Normal, "🔋 54%")));
+        assert_eq!(battery("no-rate", "format = \"{remaining}|{power}\"").render().unwrap().text, "|");
+    }
+
+    #[test]
+    fn odd_root() {
+        let dir = std::env::temp_dir().join(format!("dwmstatus-[root]-{}", std::process::id()));
+        let fixture = root::fixture("intel-discharging").path("/sys");
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(std::process::Command::new("cp").arg("-r").arg(&fixture).arg(dir.join("sys")).status().unwrap().success());
+        let mut config = root::config("battery", "", None);
+        config.root = root::Root::new(&dir);
+        let mut battery = Battery::new(&config);
+        battery.update().unwrap();
+        assert_eq!(battery.render(), Some(Text::new(Severity::Normal, "🔋 54% (3h37)")));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn charge() {
+        // µAh, converted to µWh with the voltage
+        assert_eq!(battery("charge-units", "").render(), Some(Text::new(Severity::Warn, "🔋 18% (1h58)")));
+        assert_eq!(battery("charge-units", "bad = 20").render().unwrap().severity, Severity::Bad);
+    }
+
+    #[test]
+    fn missing() {
+        let battery = battery("amd-desktop", "");
+        assert_eq!(battery.render(), None);
+        assert_eq!(battery.describe(), Some("no battery".to_string()));
+    }
+
+    #[test]
+    fn multiple() {
+        // BAT0 is held at a threshold while BAT1 drains, the mouse doesn't count
+        let both = battery("multi-battery", "format = \"{icon} {percent}% ({remaining}) {batteries}\"");
+        assert_eq!(both.render(), Some(Text::new(Severity::Normal, "🔋 59% (8h05) 99% 45%")));
+        let root = root::fixture("multi-battery");
+        assert_eq!(both.describe(), Some(format!("{}: Li-poly, SMP 01AV421, 188 cycles, 97% health; {}: Li-ion, LGC 01AV424, 95 cycles, 95% health", root.path("/sys/class/power_supply/BAT0"), root.path("/sys/class/power_supply/BAT1"))));
+        assert_eq!(battery("multi-battery", "battery = \"BAT1\"").render(), Some(Text::new(Severity::Normal, "🔋 45% (4h38)")));
+        assert_eq!(battery("multi-battery", "battery = \"hidpp_battery_0\"").describe(), Some("no battery".to_string()));
+    }
+
+    #[test]
+    fn smoothing() {
+        // a burst of load, four times the draw, for one 15s update
+        let spike = |options: &str| {
+            let mut battery = battery("intel-discharging", options);
+            let at = battery.draw.as_ref().unwrap().at;
+            battery.info.as_mut().unwrap().present_rate *= 4;
+            battery.estimate(at + time::Duration::from_secs(15));
+            battery
         };
-        Some(ret)
+        assert_eq!(spike("").render().unwrap().text, "🔋 54% (2h10)");
+        assert_eq!(spike("window = 0").render().unwrap().text, "🔋 54% (54m19)");
+        // plugging in starts over
+        let mut plugged = spike("");
+        let info = plugged.info.as_mut().unwrap();
+        info.state = BatteryState::Charge;
+        info.present_rate = 46_344_000;
+        plugged.estimate(time::Instant::now());
+        assert_eq!(plugged.render().unwrap().text, "🗲 54% (30m00)");
+    }
+
+    #[test]
+    fn health() {
+        assert_eq!(battery("intel-discharging", "format = \"{percent}% {health}%\"").render().unwrap().text, "54% 88%");
+        // of what it held new, rather than when last charged
+        assert_eq!(battery("intel-discharging", "basis = \"design\"").render().unwrap().text, "🔋 48% (3h37)");
+        assert_eq!(battery("intel-discharging", "basis = \"new\"").render().unwrap().text, "🔋 54% (3h37)");
+        // no cycle count
+        let charge = battery("charge-units", "");
+        assert!(charge.describe().unwrap().ends_with("/BAT0: Li-ion, SMP DELL 7FHHV03, 85% health"));
+    }
+
+    #[test]
+    fn power() {
+        let format = "format = \"{icon} {percent}%[ {power}W]\"\n";
+        assert_eq!(battery("intel-discharging", format).render(), Some(Text::new(Severity::Normal, "🔋 54% 7.5W")));
+        assert_eq!(battery("intel-charging", format).render().unwrap().text, "🗲 80% 24.3W");
+        // µA at the voltage
+        assert_eq!(battery("charge-units", format).render().unwrap().text, "🔋 18% 4.9W");
+        assert_eq!(battery("usb-c-threshold", format).render().unwrap().text, "🔌 80%");
+        assert_eq!(battery("intel-discharging", &format!("{}power_warn = 5", format)).render().unwrap().severity, Severity::Warn);
+        assert_eq!(battery("intel-discharging", &format!("{}power_warn = 5\npower_bad = 7", format)).render().unwrap().severity, Severity::Bad);
+        // only while draining it
+        assert_eq!(battery("intel-charging", &format!("{}power_warn = 5", format)).render().unwrap().severity, Severity::Normal);
+    }
+
+    #[test]
+    fn sources() {
+        // held at 80% by a charge threshold, on a 20V 2.25A charger
+        assert_eq!(battery("usb-c-threshold", "").render(), Some(Text::new(Severity::Normal, "🔌 80%")));
+        assert_eq!(battery("usb-c-threshold", "format = \"{state}, {source}\"").render().unwrap().text, "not charging, on USB-C 45W");
+        assert_eq!(battery("multi-battery", "battery = \"BAT0\"").render(), Some(Text::new(Severity::Normal, "🔌 99%")));
+        assert_eq!(battery("intel-charging", "format = \"{source}\"").render().unwrap().text, "on AC");
+        assert_eq!(battery("charge-units", "format = \"{source}\"").render().unwrap().text, "unplugged");
+        // no battery, but still an adapter to show
+        assert_eq!(battery("usb-c-threshold", "battery = \"BAT1\"").render(), Some(Text::normal("🔌 on USB-C 45W")));
     }
 }
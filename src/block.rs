@@ -0,0 +1,121 @@
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+use crate::config::BlockConfig;
+use crate::error;
+use crate::markup::Text;
+use crate::{battery, clock, cpu, disk, ifaddrs, load, media, mem, persistent, pulse, script, temp, threaded};
+
+// A single field of the status bar.  Each block owns whatever state it needs
+// between updates.
+pub trait Block {
+    // Called once, before the first update.
+    fn init(&mut self) {}
+
+    // Refresh the block's state.  Called every `period`, or as soon as
+    // `event_fd` becomes readable.  On error, the block is shown as failed
+    // and retried later.
+    fn update(&mut self) -> error::Result<()>;
+
+    // Format the current state, or None to hide the block.
+    fn render(&self) -> Option<Text>;
+
+    // A file descriptor which becomes readable when the block has news
+    // that shouldn't wait for the next period.
+    fn event_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    // The block was clicked with mouse `button` (1: left, 2: middle,
+    // 3: right, 4/5: scroll up/down)
+    fn click(&mut self, _button: u8) {}
+
+    // The buttons click() does something with, for bars that draw a
+    // clickable area per button
+    fn buttons(&self) -> &'static [u8] {
+        &[]
+    }
+
+    // Run a named action, as sent to the control socket (eg. "up" for
+    // volume, "next" for media)
+    fn action(&mut self, action: &str) -> error::Result<()> {
+        Err(error::Error::NotFound(format!("no action {}", action)))
+    }
+
+    // The configuration was reloaded with new options for this block.  Blocks
+    // with state worth keeping take them in place and return true, the others
+    // are replaced by a new block.
+    fn reconfigure(&mut self, _config: &BlockConfig) -> bool {
+        false
+    }
+
+    // The system was suspended: forget anything measured over time, like the
+    // byte counts speeds are worked out from.  An update follows right away.
+    fn resume(&mut self) {}
+
+    // The hardware the block found to report on, for --list-blocks
+    fn describe(&self) -> Option<String> {
+        None
+    }
+}
+
+// every mouse button, for blocks that pass clicks on
+pub static BUTTONS: &[u8] = &[1, 2, 3, 4, 5];
+
+// every block from_config() knows
+pub static NAMES: &[&str] = &["battery", "cpu", "disk", "lan", "load", "media", "ram", "temp", "time", "volume", "vpn", "wlan"];
+
+// A command that keeps running, rather than one run every period
+fn persistent(config: &BlockConfig) -> bool {
+    config.has("command") && config.get_bool("persistent", false)
+}
+
+// slow to update (or may be, for scripts), so off the main thread unless
+// asked otherwise
+fn threaded(config: &BlockConfig) -> bool {
+    config.get_bool("threaded", config.name == "media" || config.name == "wlan" || (config.has("command") && !persistent(config)))
+}
+
+pub fn timeout(config: &BlockConfig) -> Duration {
+    Duration::from_secs_f64(config.get_f64("timeout", 5.0).max(0.0))
+}
+
+pub fn from_config(config: &BlockConfig) -> Result<Box<dyn Block>, String> {
+    let threaded = threaded(config);
+    let block: Box<dyn Block + Send> = match &config.name[..] {
+        // any name will do for a script
+        _ if persistent(config) => Box::new(persistent::Persistent::new(config)),
+        _ if config.has("command") => Box::new(script::Script::new(config)),
+        "battery" => Box::new(battery::Battery::new(config)),
+        "cpu" => Box::new(cpu::Cpu::new(config)),
+        "disk" => Box::new(disk::Disk::new(config)),
+        "lan" => Box::new(ifaddrs::Network::new(ifaddrs::Kind::Lan, config)),
+        "load" => Box::new(load::Load::new(config)),
+        "media" => Box::new(media::Media::new(config)),
+        "ram" => Box::new(mem::Mem::new(config)),
+        "temp" => Box::new(temp::Temp::new(config)),
+        "time" => Box::new(clock::Clock::new(config)),
+        // the PulseAudio mainloop isn't Send, but never blocks either
+        "volume" if threaded => return Err("volume: can't be threaded".to_string()),
+        "volume" => return Ok(Box::new(pulse::Pulse::new(config))),
+        "vpn" => Box::new(ifaddrs::Network::new(ifaddrs::Kind::Vpn, config)),
+        "wlan" => Box::new(ifaddrs::Network::new(ifaddrs::Kind::Wlan, config)),
+        name => return Err(format!("unknown block: {}", name)),
+    };
+    if threaded {
+        Ok(Box::new(threaded::Threaded::new(block, timeout(config))))
+    } else {
+        Ok(block)
+    }
+}
+
+// Keep `block`, configured as `old`, for the reloaded configuration `new` if
+// it can be
+pub fn reconfigure(block: &mut Box<dyn Block>, old: &BlockConfig, new: &BlockConfig) -> bool {
+    if old.same_options(new) {
+        true
+    } else if threaded(old) != threaded(new) {
+        false
+    } else {
+        block.reconfigure(new)
+    }
+}
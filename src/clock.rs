@@ -0,0 +1,33 @@
+use chrono::prelude::*;
+use crate::block::Block;
+use crate::config::BlockConfig;
+use crate::error::Result;
+use crate::markup::Text;
+
+static FORMAT: &str = "%Y-%m-%d %H:%M";
+
+pub struct Clock {
+    format: String,
+    now: Option<DateTime<Local>>,
+}
+
+impl Clock {
+    pub fn new(config: &BlockConfig) -> Self {
+        Self { format: config.get_str("format", FORMAT).to_string(), now: None }
+    }
+}
+
+impl Block for Clock {
+    fn update(&mut self) -> Result<()> {
+        self.now = Some(Local::now());
+        Ok(())
+    }
+
+    fn describe(&self) -> Option<String> {
+        self.now.map(|now| format!("local time, UTC{}", now.offset()))
+    }
+
+    fn render(&self) -> Option<Text> {
+        self.now.map(|now| Text::normal(now.format(&self.format).to_string()))
+    }
+}
@@ -0,0 +1,353 @@
+use crate::format::Format;
+use crate::markup::{self, Markup};
+use crate::root::Root;
+use serde::Deserialize;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::{env, ffi, fs, io, mem, path, ptr, time};
+
+// used when no configuration file exists
+static DEFAULT_CONFIG: &str = include_str!("../dwmstatus.toml");
+static DEFAULT_PERIOD: f64 = 5.0;
+// a day; anything rarer can wait for a signal
+static MAX_PERIOD: f64 = 86400.0;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // mark blocks for dwm's statuscmd patch
+    #[serde(default)]
+    pub statuscmd: bool,
+    // how colours are written for the x and stdout outputs
+    #[serde(default = "default_markup")]
+    pub markup: Markup,
+    // colours for outputs that take them, instead of statuscolors bytes
+    #[serde(default)]
+    pub colors: Colors,
+    // milliseconds to wait for more changes before writing the bar
+    #[serde(default)]
+    coalesce: u64,
+    #[serde(default, rename = "block")]
+    pub blocks: Vec<BlockConfig>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    pub good: String,
+    pub warn: String,
+    pub bad: String,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self { good: "#00ff00".to_string(), warn: "#ffff00".to_string(), bad: "#ff0000".to_string() }
+    }
+}
+
+#[derive(Clone, Deserialize)]
+pub struct BlockConfig {
+    pub name: String,
+    #[serde(default = "default_period")]
+    period: f64,
+    // refresh when SIGRTMIN+signal is received
+    #[serde(default)]
+    signal: Option<u32>,
+    // run on click instead of the block's own action, with $BLOCK_BUTTON set
+    #[serde(default)]
+    pub on_click: Option<String>,
+    // everything else is specific to the block
+    #[serde(flatten)]
+    options: toml::Table,
+    // where the block reads /proc, /sys etc. from
+    #[serde(skip)]
+    pub root: Root,
+}
+
+fn default_period() -> f64 {
+    DEFAULT_PERIOD
+}
+
+fn default_markup() -> Markup {
+    Markup::StatusColors
+}
+
+impl Config {
+    // Load the configuration from `path`, or from the default location if
+    // none was given.  A missing file at the default location is not an error.
+    pub fn load(path: Option<&path::Path>) -> Result<Self, String> {
+        let explicit = path.is_some();
+        match Self::path(path) {
+            Some(path) => match fs::read_to_string(&path) {
+                Ok(data) => Self::parse(&data).map_err(|err| format!("{}: {}", path.display(), err)),
+                Err(err) if !explicit && err.kind() == std::io::ErrorKind::NotFound => Self::parse(DEFAULT_CONFIG),
+                Err(err) => Err(format!("{}: {}", path.display(), err)),
+            },
+            None => Self::parse(DEFAULT_CONFIG),
+        }
+    }
+
+    // Point every block at `root` instead of /
+    pub fn set_root(&mut self, root: &Root) {
+        for block in &mut self.blocks {
+            block.root = root.clone();
+        }
+    }
+
+    pub fn coalesce(&self) -> time::Duration {
+        time::Duration::from_millis(self.coalesce)
+    }
+
+    // Where load() reads from
+    pub fn path(path: Option<&path::Path>) -> Option<path::PathBuf> {
+        match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => default_path(),
+        }
+    }
+
+    pub fn parse(data: &str) -> Result<Self, String> {
+        let config: Self = toml::from_str(data).map_err(|err| err.to_string())?;
+        if config.blocks.is_empty() {
+            return Err("no blocks configured".to_string());
+        }
+        for color in &[&config.colors.good, &config.colors.warn, &config.colors.bad] {
+            if markup::rgb(color).is_none() {
+                return Err(format!("colors: {} is not #rrggbb", color));
+            }
+        }
+        for block in &config.blocks {
+            if block.period.is_nan() || block.period <= 0.0 || block.period > MAX_PERIOD {
+                return Err(format!("block {}: period must be positive and at most {}", block.name, MAX_PERIOD));
+            }
+            if let Some(signal) = block.signal {
+                let max = libc::SIGRTMAX() - libc::SIGRTMIN();
+                if signal as i32 > max {
+                    return Err(format!("block {}: signal must be at most {}", block.name, max));
+                }
+                if config.statuscmd && config.markup.reserves(signal as u8) {
+                    return Err(format!("block {}: signal {} is a colour byte for this markup, use 6 or more", block.name, signal));
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+// $XDG_CONFIG_HOME/dwmstatus/config.toml, falling back to ~/.config
+fn default_path() -> Option<path::PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => path::PathBuf::from(dir),
+        _ => path::PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("dwmstatus").join("config.toml"))
+}
+
+impl BlockConfig {
+    // `name` with every option at its default
+    pub fn named(name: &str) -> Self {
+        Self { name: name.to_string(), period: DEFAULT_PERIOD, signal: None, on_click: None, options: toml::Table::new(), root: Root::default() }
+    }
+
+    pub fn period(&self) -> time::Duration {
+        time::Duration::from_secs_f64(self.period)
+    }
+
+    // whether updates fall on wall-clock multiples of the period, eg. the top
+    // of the minute, rather than a period after the last one
+    pub fn align(&self) -> bool {
+        self.get_bool("align", self.name == "time")
+    }
+
+    pub fn signal(&self) -> Option<libc::c_int> {
+        self.signal.map(|signal| libc::SIGRTMIN() + signal as libc::c_int)
+    }
+
+    // whether the block-specific options are the same as `other`'s
+    pub fn same_options(&self, other: &BlockConfig) -> bool {
+        self.options == other.options
+    }
+
+    pub fn has(&self, key: &str) -> bool {
+        self.options.contains_key(key)
+    }
+
+    fn get(&self, key: &str) -> Option<&toml::Value> {
+        self.options.get(key)
+    }
+
+    // Warn about a bad value for `key`, for blocks that check their own
+    pub fn invalid<T>(&self, key: &str, expected: &str, default: T) -> T {
+        eprintln!("dwmstatus: block {}: option {} should be {}, using default", self.name, key, expected);
+        default
+    }
+
+    pub fn get_bool(&self, key: &str, default: bool) -> bool {
+        match self.get(key) {
+            None => default,
+            Some(toml::Value::Boolean(value)) => *value,
+            Some(_) => self.invalid(key, "a boolean", default),
+        }
+    }
+
+    pub fn get_f64(&self, key: &str, default: f64) -> f64 {
+        match self.get(key) {
+            None => default,
+            Some(toml::Value::Float(value)) => *value,
+            Some(toml::Value::Integer(value)) => *value as f64,
+            Some(_) => self.invalid(key, "a number", default),
+        }
+    }
+
+    pub fn get_str<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        match self.get(key) {
+            None => default,
+            Some(toml::Value::String(value)) => value,
+            Some(_) => self.invalid(key, "a string", default),
+        }
+    }
+
+    // A format template with the block's named values
+    pub fn get_format(&self, key: &str, default: &str) -> Format {
+        match Format::parse(self.get_str(key, default)) {
+            Ok(format) => format,
+            Err(err) => self.invalid(key, &format!("a valid format ({})", err), Format::parse(default).unwrap()),
+        }
+    }
+
+    // A size in bytes, either as a plain integer or a string with a
+    // binary suffix, eg. "100M" or "1G"
+    pub fn get_bytes(&self, key: &str, default: u64) -> u64 {
+        match self.get(key) {
+            None => default,
+            Some(toml::Value::Integer(value)) if *value >= 0 => *value as u64,
+            Some(toml::Value::String(value)) => match parse_bytes(value) {
+                Some(value) => value,
+                None => self.invalid(key, "a size", default),
+            },
+            Some(_) => self.invalid(key, "a size", default),
+        }
+    }
+}
+
+// Notices when the configuration file is written.  Watches its directory,
+// since editors tend to replace the file rather than write to it.
+pub struct Watch {
+    fd: RawFd,
+    name: ffi::OsString,
+}
+
+impl Watch {
+    pub fn new(path: &path::Path) -> io::Result<Self> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => path::Path::new("."),
+        };
+        let name = path.file_name().ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?.to_os_string();
+        let dir_c = ffi::CString::new(dir.as_os_str().as_bytes()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let result = Self { fd, name };
+        if unsafe { libc::inotify_add_watch(fd, dir_c.as_ptr(), libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(result)
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    // whether the file was written since the last call
+    pub fn changed(&self) -> bool {
+        let mut result = false;
+        let mut buf = [0u8; 4096];
+        loop {
+            let count = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if count <= 0 {
+                break;
+            }
+            let mut offset = 0;
+            while offset + mem::size_of::<libc::inotify_event>() <= count as usize {
+                let event = unsafe { ptr::read_unaligned(buf[offset..].as_ptr() as *const libc::inotify_event) };
+                let start = offset + mem::size_of::<libc::inotify_event>();
+                let name = &buf[start..start + event.len as usize];
+                let end = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+                if ffi::OsStr::from_bytes(&name[..end]) == self.name {
+                    result = true;
+                }
+                offset = start + event.len as usize;
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn parse_bytes(value: &str) -> Option<u64> {
+    let value = value.trim().trim_end_matches('B');
+    let (num, shift) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 10),
+        'M' => (&value[..value.len() - 1], 20),
+        'G' => (&value[..value.len() - 1], 30),
+        'T' => (&value[..value.len() - 1], 40),
+        _ => (value, 0),
+    };
+    let num: f64 = num.trim().parse().ok()?;
+    if num < 0.0 {
+        return None;
+    }
+    Some((num * (1u64 << shift) as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_bytes, Config, DEFAULT_CONFIG};
+
+    #[test]
+    fn default_config() {
+        let config = Config::parse(DEFAULT_CONFIG).unwrap();
+        assert_eq!(config.blocks.len(), 12);
+        let ram = config.blocks.iter().find(|block| block.name == "ram").unwrap();
+        assert_eq!(ram.get_bytes("free_warn", 0), 1024 * 1024 * 1024);
+        assert_eq!(ram.get_bytes("free_bad", 0), 100 * 1024 * 1024);
+        let temp = config.blocks.iter().find(|block| block.name == "temp").unwrap();
+        assert_eq!(temp.get_f64("bad", 0.0), 75.0);
+    }
+
+    #[test]
+    fn periods() {
+        let block = |period: &str| Config::parse(&format!("[[block]]\nname = \"time\"\nperiod = {}", period));
+        assert!(block("0.5").is_ok());
+        assert!(block("86400").is_ok());
+        assert!(block("0").is_err());
+        assert!(block("nan").is_err());
+        assert!(block("inf").is_err());
+        assert!(block("1e300").is_err());
+    }
+
+    #[test]
+    fn signals() {
+        let block = |options: &str, signal: u8| Config::parse(&format!("{}\n[[block]]\nname = \"volume\"\nsignal = {}", options, signal));
+        assert!(block("", 2).is_ok());
+        assert!(block("statuscmd = true", 2).is_err());
+        assert!(block("statuscmd = true", 6).is_ok());
+        assert!(block("statuscmd = true\nmarkup = \"status2d\"", 2).is_ok());
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_bytes("512"), Some(512));
+        assert_eq!(parse_bytes("1.5k"), Some(1536));
+        assert_eq!(parse_bytes("100MB"), Some(100 * 1024 * 1024));
+        assert_eq!(parse_bytes("-1G"), None);
+        assert_eq!(parse_bytes("lots"), None);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::output::LineReader;
+use std::collections::HashMap;
+use std::io::prelude::*;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::{env, fs, io, net, path, time};
+
+// how long a client gets to take a reply, with the bar waiting
+static REPLY_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+// A request read from the control socket, one per line:
+//
+//   dump                    the bar and every field, as JSON
+//   refresh [NAME]          update the NAME blocks (or all of them) now
+//   hide NAME / show NAME   take the NAME blocks off the bar, or put them back
+//   action NAME ACTION      eg. "action volume up", "action media next"
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Dump,
+    Refresh(Option<String>),
+    Hide(String),
+    Show(String),
+    Action(String, String),
+    // what the block found to report on, eg. each battery's health
+    Describe(String),
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match &words[..] {
+            ["dump"] => Ok(Command::Dump),
+            ["refresh"] => Ok(Command::Refresh(None)),
+            ["refresh", name] => Ok(Command::Refresh(Some(name.to_string()))),
+            ["hide", name] => Ok(Command::Hide(name.to_string())),
+            ["show", name] => Ok(Command::Show(name.to_string())),
+            ["action", name, action] => Ok(Command::Action(name.to_string(), action.to_string())),
+            ["describe", name] => Ok(Command::Describe(name.to_string())),
+            [] => Err("empty command".to_string()),
+            [command, ..] => Err(format!("bad command: {}", command)),
+        }
+    }
+}
+
+struct Client {
+    stream: UnixStream,
+    input: LineReader,
+}
+
+// $XDG_RUNTIME_DIR/dwmstatus.sock, which scripts can talk to with eg.
+// `echo "action volume up" | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/dwmstatus.sock`.
+// Each command is answered with a line: "ok", "error: ..." or the dump.
+pub struct Control {
+    listener: UnixListener,
+    clients: HashMap<RawFd, Client>,
+}
+
+pub fn default_path() -> Option<path::PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR").map(|dir| path::Path::new(&dir).join("dwmstatus.sock"))
+}
+
+impl Control {
+    pub fn bind(path: &path::Path) -> io::Result<Self> {
+        // a socket left behind by an earlier dwmstatus, unless it's still running
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "another dwmstatus is listening"));
+        }
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener, clients: HashMap::new() })
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+
+    // take the pending connections, returning their fds to watch
+    pub fn accept(&mut self) -> Vec<RawFd> {
+        let mut result = vec![];
+        while let Ok((stream, _)) = self.listener.accept() {
+            // applies while replying, when the stream blocks
+            let _ = stream.set_write_timeout(Some(REPLY_TIMEOUT));
+            let fd = stream.as_raw_fd();
+            self.clients.insert(fd, Client { input: LineReader::new(fd), stream });
+            result.push(fd);
+        }
+        result
+    }
+
+    // the complete lines `fd` has sent, or None once it's gone
+    pub fn read(&mut self, fd: RawFd) -> Option<Vec<String>> {
+        let client = self.clients.get_mut(&fd)?;
+        let lines = client.input.lines();
+        if client.input.eof && lines.is_empty() {
+            None
+        } else {
+            Some(lines)
+        }
+    }
+
+    pub fn reply(&mut self, fd: RawFd, reply: &str) {
+        if let Some(client) = self.clients.get_mut(&fd) {
+            // all of it, even a dump bigger than the socket's buffer; a
+            // client that doesn't read it in time gets cut off instead
+            let stream = &mut client.stream;
+            let result = stream.set_nonblocking(false).and_then(|()| stream.write_all(format!("{}\n", reply).as_bytes()));
+            let _ = stream.set_nonblocking(true);
+            if let Err(err) = result {
+                eprintln!("dwmstatus: control: {}", err);
+                let _ = stream.shutdown(net::Shutdown::Write);
+            }
+        }
+    }
+
+    pub fn close(&mut self, fd: RawFd) {
+        self.clients.remove(&fd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, Control};
+    use std::io::prelude::*;
+    use std::os::unix::net::UnixStream;
+    use std::{env, fs, process, thread};
+
+    #[test]
+    fn commands() {
+        assert_eq!(Command::parse("dump"), Ok(Command::Dump));
+        assert_eq!(Command::parse(" refresh  "), Ok(Command::Refresh(None)));
+        assert_eq!(Command::parse("refresh wlan"), Ok(Command::Refresh(Some("wlan".to_string()))));
+        assert_eq!(Command::parse("action volume up"), Ok(Command::Action("volume".to_string(), "up".to_string())));
+        assert_eq!(Command::parse("describe battery"), Ok(Command::Describe("battery".to_string())));
+        assert!(Command::parse("").is_err());
+        assert!(Command::parse("hide").is_err());
+        assert!(Command::parse("frobnicate wlan").is_err());
+    }
+
+    #[test]
+    fn long_reply() {
+        let path = env::temp_dir().join(format!("dwmstatus-test-{}.sock", process::id()));
+        let mut control = Control::bind(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        let fd = control.accept()[0];
+        // far more than the socket buffers, read as it comes
+        let reply = "x".repeat(4 << 20);
+        let reader = thread::spawn(move || {
+            let mut received = String::new();
+            client.read_to_string(&mut received).unwrap();
+            received
+        });
+        control.reply(fd, &reply);
+        control.close(fd);
+        assert_eq!(reader.join().unwrap(), reply + "\n");
+        let _ = fs::remove_file(&path);
+    }
+}
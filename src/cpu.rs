@@ -1,20 +1,91 @@
 use std::io::prelude::*;
 use std::{fs, io};
-use std::str::FromStr;
+use crate::block::Block;
+use crate::config::BlockConfig;
+use crate::error::{self, Error, Result};
+use crate::format::{Format, Values};
+use crate::markup::{Severity, Text};
 use crate::prelude::*;
+use crate::root::Root;
 
-pub fn status() -> String {
-    let fd = io::BufReader::new(fs::File::open("/proc/cpuinfo").unwrap());
+static FORMAT: &str = "{freq}Hz";
 
-    for line in fd.lines() {
-        let line = line.unwrap();
-        if line.starts_with("cpu MHz") {
-            let columns: Vec<&str> = line.splitn(2, ":").collect();
-            assert_eq!(columns.len(), 2);
-            let hz = f64::from_str(columns[1].trim()).unwrap() * 1024.0 * 1024.0;
-            return prefixed(hz) + "Hz";
+pub struct Cpu {
+    root: Root,
+    format: Format,
+    // as /proc/cpuinfo gives it
+    mhz: Option<f64>,
+    model: Option<String>,
+}
+
+impl Cpu {
+    pub fn new(config: &BlockConfig) -> Self {
+        Self { root: config.root.clone(), format: config.get_format("format", FORMAT), mhz: None, model: None }
+    }
+}
+
+impl Block for Cpu {
+    fn update(&mut self) -> Result<()> {
+        let path = &self.root.path("/proc/cpuinfo");
+        let fd = io::BufReader::new(fs::File::open(path).map_err(error::io(path))?);
+
+        self.mhz = None;
+        for line in fd.lines() {
+            let line = line.map_err(error::io(path))?;
+            // comes before the MHz
+            if let Some(model) = line.strip_prefix("model name") {
+                self.model = Some(model.trim_start().trim_start_matches(':').trim().to_string());
+            }
+            if line.starts_with("cpu MHz") {
+                let columns: Vec<&str> = line.splitn(2, ":").collect();
+                if columns.len() != 2 {
+                    return Err(Error::Parse(format!("{}: unexpected line {:?}", path, line)));
+                }
+                self.mhz = Some(error::parse::<f64>(path, columns[1])?);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn describe(&self) -> Option<String> {
+        Some(format!("{} ({})", self.root.path("/proc/cpuinfo"), self.model.as_deref().unwrap_or("unknown model")))
+    }
+
+    fn render(&self) -> Option<Text> {
+        match self.mhz {
+            Some(mhz) => {
+                let mut values = Values::new();
+                values.insert("freq", prefixed(mhz * 1024.0 * 1024.0));
+                values.insert("mhz", format!("{:0.0}", mhz));
+                Some(Text::normal(self.format.render(&values)))
+            },
+            None => Some(Text::new(Severity::Warn, "?")),
         }
     }
-    WARN.to_string() + "?"
 }
 
+#[cfg(test)]
+mod tests {
+    use super::Cpu;
+    use crate::block::Block;
+    use crate::markup::Text;
+    use crate::root;
+
+    fn cpu(fixture: &str, options: &str) -> Cpu {
+        let config = root::config("cpu", options, Some(fixture));
+        Cpu::new(&config)
+    }
+
+    #[test]
+    fn mhz() {
+        let mut first = cpu("intel-discharging", "format = \"{mhz} MHz\"");
+        first.update().unwrap();
+        assert_eq!(first.render(), Some(Text::normal("2395 MHz")));
+        let mut freq = cpu("intel-discharging", "");
+        freq.update().unwrap();
+        assert_eq!(freq.render(), Some(Text::normal("2.3GHz")));
+        assert!(freq.describe().unwrap().ends_with("cpuinfo (Intel(R) Core(TM) i7-8550U CPU @ 1.80GHz)"));
+        assert!(cpu("multi-battery", "").update().is_err());
+    }
+}
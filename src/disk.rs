@@ -1,45 +1,92 @@
+use crate::block::Block;
+use crate::config::BlockConfig;
+use crate::error::{Error, Result};
+use crate::format::{Format, Values};
+use crate::markup::{Severity, Text};
 use crate::prelude::*;
+use crate::root::Root;
 use libc::{endmntent, getmntent, setmntent, statvfs};
 use std::{ffi, mem};
 
 static ICON: &str = "🖴";
+static FORMAT: &str = "{icon} {free}B";
 static PATH: &str = "/";
+static FREE_WARN: f64 = 10.0;  // percent
 
-pub fn status() -> String {
-    let mut buf = mem::MaybeUninit::<statvfs>::uninit();
-    let mut mounted = false;
-    let path = ffi::CString::new(PATH).unwrap();
-
-    if unsafe { statvfs(path.as_ptr(), buf.as_mut_ptr()) } == -1 {
-        // If statvfs errors, e.g., due to the path not existing,
-        // we consider the device not mounted.
-        mounted = false;
-    } else {
-        let mtab_cstr = ffi::CString::new("/etc/mtab").unwrap();
-        let r_cstr = ffi::CString::new("r").unwrap();
-        let mntentfile = unsafe { setmntent(mtab_cstr.as_ptr(), r_cstr.as_ptr()) };
-
-        loop {
-            let m = unsafe { getmntent(mntentfile) };
-            if m.is_null() {
-                break;
+pub struct Disk {
+    root: Root,
+    format: Format,
+    path: String,
+    free_warn: f64,
+    stat: Option<statvfs>,
+}
+
+impl Disk {
+    pub fn new(config: &BlockConfig) -> Self {
+        Self {
+            root: config.root.clone(),
+            format: config.get_format("format", FORMAT),
+            path: config.get_str("path", PATH).to_string(),
+            free_warn: config.get_f64("warn_percent", FREE_WARN),
+            stat: None,
+        }
+    }
+}
+
+impl Block for Disk {
+    fn update(&mut self) -> Result<()> {
+        let mut buf = mem::MaybeUninit::<statvfs>::uninit();
+        let mut mounted = false;
+        let path = ffi::CString::new(&self.path[..]).map_err(|_| Error::Parse(format!("bad path {:?}", self.path)))?;
+
+        // the live filesystem, whatever the root
+        if unsafe { statvfs(path.as_ptr(), buf.as_mut_ptr()) } == -1 {
+            // If statvfs errors, e.g., due to the path not existing,
+            // we consider the device not mounted.
+            mounted = false;
+        } else {
+            let mtab_cstr = ffi::CString::new(self.root.path("/etc/mtab")).map_err(|_| Error::Parse("bad root".to_string()))?;
+            let r_cstr = ffi::CString::new("r").unwrap();
+            let mntentfile = unsafe { setmntent(mtab_cstr.as_ptr(), r_cstr.as_ptr()) };
+            if mntentfile.is_null() {
+                return Err(Error::Os("setmntent", std::io::Error::last_os_error()));
             }
-            let mnt_dir = unsafe { ffi::CStr::from_ptr((*m).mnt_dir) };
-            if mnt_dir.to_str().unwrap() == PATH {
-                mounted = true;
-                break;
+
+            loop {
+                let m = unsafe { getmntent(mntentfile) };
+                if m.is_null() {
+                    break;
+                }
+                let mnt_dir = unsafe { ffi::CStr::from_ptr((*m).mnt_dir) };
+                if mnt_dir.to_bytes() == self.path.as_bytes() {
+                    mounted = true;
+                    break;
+                }
             }
+            unsafe { endmntent(mntentfile) };
         }
-        unsafe { endmntent(mntentfile) };
+        self.stat = if mounted { Some(unsafe { buf.assume_init() }) } else { None };
+        Ok(())
+    }
+
+    fn describe(&self) -> Option<String> {
+        Some(if self.stat.is_some() { self.path.clone() } else { format!("{} (not mounted)", self.path) })
     }
-    if !mounted {
-        BAD.to_string() + ICON + " ?"
-    } else {
-        let buf = unsafe { buf.assume_init() };
-        let percent_free = 100.0 * buf.f_bfree as f64 / buf.f_blocks as f64;
-        let mut result = if percent_free < 10.0 { WARN } else { "" }.to_string() + ICON + " ";
-        result += &prefixed(buf.f_bsize as f64 * buf.f_bavail as f64);
-        result += "B";
-        result
+
+    fn render(&self) -> Option<Text> {
+        match &self.stat {
+            None => Some(Text::new(Severity::Bad, ICON.to_string() + " ?")),
+            Some(buf) => {
+                let percent_free = 100.0 * buf.f_bfree as f64 / buf.f_blocks as f64;
+                let severity = if percent_free < self.free_warn { Severity::Warn } else { Severity::Normal };
+                let mut values = Values::new();
+                values.insert("icon", ICON.to_string());
+                values.insert("free", prefixed(buf.f_bsize as f64 * buf.f_bavail as f64));
+                values.insert("total", prefixed(buf.f_frsize as f64 * buf.f_blocks as f64));
+                values.insert("free_percent", format!("{:0.0}", percent_free));
+                values.insert("path", self.path.clone());
+                Some(Text::new(severity, self.format.render(&values)))
+            },
+        }
     }
 }
@@ -0,0 +1,39 @@
+use std::str::FromStr;
+use std::{fmt, io, result};
+
+// Why a block couldn't update
+#[derive(Debug)]
+pub enum Error {
+    // a libc call failed
+    Os(&'static str, io::Error),
+    // reading a file failed
+    Io(String, io::Error),
+    // the data read didn't look as expected
+    Parse(String),
+    // the device the block reports on doesn't exist
+    NotFound(String),
+    // a library reported an error of its own
+    Lib(String),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Os(call, err) => write!(f, "{}: {}", call, err),
+            Error::Io(path, err) => write!(f, "{}: {}", path, err),
+            Error::Parse(msg) | Error::NotFound(msg) | Error::Lib(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+// Wrap an io::Error with the path it came from
+pub fn io(path: &str) -> impl FnOnce(io::Error) -> Error + '_ {
+    move |err| Error::Io(path.to_string(), err)
+}
+
+// Parse `value` as read from `what`
+pub fn parse<T: FromStr>(what: &str, value: &str) -> Result<T> {
+    value.trim().parse().map_err(|_| Error::Parse(format!("{}: unexpected value {:?}", what, value)))
+}
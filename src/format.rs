@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+// The named values a block offers to its format
+pub type Values = HashMap<&'static str, String>;
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Value(String),
+    // only shown when every value inside it is available
+    Section(Vec<Part>),
+}
+
+// A user format, eg. "{icon} {percent}%[ ({remaining})]".  `{name}` is
+// replaced by the block's value of that name, `[...]` is dropped unless all
+// the values inside it are available, and `\` escapes the next character.
+#[derive(Debug, PartialEq)]
+pub struct Format {
+    parts: Vec<Part>,
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        let mut chars = format.chars();
+        let parts = parse_parts(&mut chars, false)?;
+        Ok(Self { parts })
+    }
+
+    pub fn render(&self, values: &Values) -> String {
+        let mut result = String::new();
+        render_parts(&self.parts, values, &mut result);
+        result
+    }
+}
+
+fn parse_parts(chars: &mut std::str::Chars, in_section: bool) -> Result<Vec<Part>, String> {
+    let mut parts = vec![];
+    let mut literal = String::new();
+    loop {
+        let c = match chars.next() {
+            Some(c) => c,
+            None if in_section => return Err("unclosed [".to_string()),
+            None => break,
+        };
+        match c {
+            '\\' => match chars.next() {
+                Some(c) => literal.push(c),
+                None => return Err("trailing \\".to_string()),
+            },
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err("unclosed {".to_string()),
+                    }
+                }
+                if name.is_empty() {
+                    return Err("empty {}".to_string());
+                }
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(literal.split_off(0)));
+                }
+                parts.push(Part::Value(name));
+            },
+            '[' => {
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(literal.split_off(0)));
+                }
+                parts.push(Part::Section(parse_parts(chars, true)?));
+            },
+            ']' if in_section => break,
+            '}' | ']' => return Err(format!("unmatched {}", c)),
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
+fn available(parts: &[Part], values: &Values) -> bool {
+    parts.iter().all(|part| match part {
+        Part::Value(name) => values.contains_key(&name[..]),
+        _ => true,
+    })
+}
+
+fn render_parts(parts: &[Part], values: &Values, result: &mut String) {
+    for part in parts {
+        match part {
+            Part::Literal(text) => *result += text,
+            Part::Value(name) => {
+                if let Some(value) = values.get(&name[..]) {
+                    *result += value;
+                }
+            },
+            Part::Section(parts) => {
+                if available(parts, values) {
+                    render_parts(parts, values, result);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Format, Values};
+
+    #[test]
+    fn render() {
+        let mut values = Values::new();
+        values.insert("percent", "50".to_string());
+        let format = Format::parse("{percent}%[ ({remaining})][ {percent}\\[\\]]").unwrap();
+        assert_eq!(format.render(&values), "50% 50[]");
+        values.insert("remaining", "1h05".to_string());
+        assert_eq!(format.render(&values), "50% (1h05) 50[]");
+        let nested = Format::parse("[a[{b}]{c}]").unwrap();
+        values.insert("c", "c".to_string());
+        assert_eq!(nested.render(&values), "ac");
+        assert_eq!(Format::parse("{missing}x").unwrap().render(&values), "x");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Format::parse("[{a}").is_err());
+        assert!(Format::parse("{a").is_err());
+        assert!(Format::parse("{}").is_err());
+        assert!(Format::parse("a]").is_err());
+        assert!(Format::parse("a\\").is_err());
+    }
+}
@@ -1,35 +1,65 @@
 use std::{ffi, fs, mem, ptr, time};
+use std::os::unix::io::RawFd;
 use std::str::FromStr;
-use libc::{freeifaddrs, getifaddrs, getnameinfo};
+use libc::{bind, freeifaddrs, getifaddrs, getnameinfo, recv, socket};
+use crate::block::Block;
+use crate::config::BlockConfig;
+use crate::error::Result;
+use crate::format::{Format, Values};
+use crate::markup::{Severity, Text};
 use crate::prelude::*;
+use crate::root::Root;
 use crate::wlaninfo;
 
 static ICON_VPN: &str = "🔒";
 static ICON_WLAN: &str = "📶";
 static ICON_LAN: &str = "🖧";
-static ICON_UP: &str = "↑";
-static ICON_DOWN: &str = "↓";
+static FORMAT: &str = "{icon} {ip}[ ↓{rx}][ ↑{tx}]";
+static FORMAT_WLAN: &str = "{icon} {ip} ({ssid}[ {quality}%])[ ↓{rx}][ ↑{tx}]";
+static FORMAT_DOWN: &str = "{icon} down";
+static QUALITY_WARN: f64 = 50.0;
 
-pub struct Interfaces {
+pub enum Kind {
+    Lan,
+    Wlan,
+    Vpn,
+}
+
+// A single network block.  Each one tracks the interfaces on its own, so the
+// byte counters used for speeds belong to the block.
+pub struct Network {
+    kind: Kind,
+    interfaces: Interfaces,
+    netlink: RawFd,
+    wlan_info: Option<wlaninfo::WLanInfo>,
+    show_down: bool,
+    quality_warn: f64,
+    format: Format,
+    format_down: Format,
+}
+
+struct Interfaces {
+    root: Root,
     lan: Option<IFAddr>,
     wlan: Option<IFAddr>,
     vpn: Option<IFAddr>,
     last: time::Instant,
-    show_lan_down: bool,
-    show_wlan_down: bool,
-    show_vpn_down: bool,
 }
 
 struct IFAddrs {
     ifap: *mut libc::ifaddrs,
     cp: *const libc::ifaddrs,
+    // for the interfaces' statistics
+    root: Root,
 }
 
 impl IFAddrs {
-    fn new() -> Self {
+    fn new(root: &Root) -> Result<Self> {
         let mut ifap = ptr::null_mut();
-        perror_check!(getifaddrs(&mut ifap));
-        IFAddrs { ifap: ifap, cp: ifap }
+        // the live interfaces, whatever the root; only their statistics
+        // are read under it
+        os_check!(getifaddrs(&mut ifap))?;
+        Ok(IFAddrs { ifap: ifap, cp: ifap, root: root.clone() })
     }
 }
 
@@ -49,18 +79,18 @@ impl Iterator for IFAddrs {
                     0
                 };
                 let (addr, has_addr) = if ret != 0 {
-                    let err_str = unsafe { ffi::CStr::from_ptr(libc::gai_strerror(ret)) }.to_str().unwrap();
+                    let err_str = unsafe { ffi::CStr::from_ptr(libc::gai_strerror(ret)) }.to_string_lossy();
                     eprintln!("getnameinfo(): {}", err_str);
-                    ("error", false)
+                    ("error".into(), false)
                 } else if addr_raw[0] == 0 {
-                    ("no IP", false)
+                    ("no IP".into(), false)
                 } else {
-                    (unsafe { ffi::CStr::from_ptr(addr_raw.as_ptr()) }.to_str().unwrap(), true)
+                    (unsafe { ffi::CStr::from_ptr(addr_raw.as_ptr()) }.to_string_lossy(), true)
                 };
-                let name = unsafe { ffi::CStr::from_ptr(ifa.ifa_name) }.to_str().unwrap().to_string();
+                let name = unsafe { ffi::CStr::from_ptr(ifa.ifa_name) }.to_string_lossy().into_owned();
 
                 let rx_bytes = {
-                    match fs::read_to_string("/sys/class/net/".to_string() + &name + "/statistics/rx_bytes") {
+                    match fs::read_to_string(self.root.path(&format!("/sys/class/net/{}/statistics/rx_bytes", name))) {
                         Ok(contents) => {
                             //println!("rx bytes for {}: {}", &name, &contents.trim_end());
                             match u64::from_str(&contents.trim_end()) {
@@ -73,7 +103,7 @@ impl Iterator for IFAddrs {
                 };
 
                 let tx_bytes = {
-                    match fs::read_to_string("/sys/class/net/".to_string() + &name + "/statistics/tx_bytes") {
+                    match fs::read_to_string(self.root.path(&format!("/sys/class/net/{}/statistics/tx_bytes", name))) {
                         Ok(contents) => {
                             //println!("tx bytes for {}: {}", &name, &contents.trim_end());
                             match u64::from_str(&contents.trim_end()) {
@@ -110,21 +140,29 @@ impl Drop for IFAddrs {
     }
 }
 
-fn speed_to_str(speed: Option<f64>, icon: &str) -> String {
-    match speed {
-        Some(speed) => {
-            let mut result = " ".to_string();
-            result += icon;
-            if speed < 1024. {
-                result += &prefixed0(speed);
-            } else {
-                result += &prefixed(speed);
-            }
-            result += "B/s";
-            result
-        },
-        None => "".to_string(),
+fn speed_to_str(speed: f64) -> String {
+    let mut result = if speed < 1024. {
+        prefixed0(speed)
+    } else {
+        prefixed(speed)
+    };
+    result += "B/s";
+    result
+}
+
+// The values every interface has, where known
+fn ifaddr_values(ifaddr: &IFAddr, icon: &str) -> Values {
+    let mut values = Values::new();
+    values.insert("icon", icon.to_string());
+    values.insert("ip", ifaddr.addr.clone());
+    values.insert("iface", ifaddr.name.clone());
+    if let Some(speed) = ifaddr.rx_speed {
+        values.insert("rx", speed_to_str(speed));
     }
+    if let Some(speed) = ifaddr.tx_speed {
+        values.insert("tx", speed_to_str(speed));
+    }
+    values
 }
 
 #[derive(Debug)]
@@ -204,18 +242,28 @@ impl IFAddr {
 }
 
 impl Interfaces {
-    pub fn new(show_lan_down: bool, show_wlan_down: bool, show_vpn_down: bool) -> Self {
-        Self { lan: None, wlan: None, vpn: None, last: time::Instant::now() - time::Duration::new(5, 0), show_lan_down, show_wlan_down, show_vpn_down }
+    fn new(root: Root) -> Self {
+        Self { root, lan: None, wlan: None, vpn: None, last: time::Instant::now() - time::Duration::new(5, 0) }
+    }
+
+    // Start the speeds over, from the next update
+    fn reset(&mut self) {
+        self.lan = None;
+        self.wlan = None;
+        self.vpn = None;
+        self.last = time::Instant::now() - time::Duration::new(5, 0);
     }
 
     // Return the IP address for the given interface or "no IP" if the
     // interface is up and running but hasn't got an IP address yet
-    pub fn update(&mut self) {
+    fn update(&mut self) -> Result<()> {
         let now = time::Instant::now();
-        if now - self.last < time::Duration::new(1, 0) {
-            return;
+        // speeds over shorter intervals are just noise
+        if now - self.last < time::Duration::from_millis(100) {
+            return Ok(());
         }
 
+        let ifaddrs = IFAddrs::new(&self.root)?;
         let interval = now - self.last;
         self.last = now;
 
@@ -227,7 +275,7 @@ impl Interfaces {
         let mut vpn_has_addr = false;
         let mut wlan_has_addr = false;
 
-        for mut iface in IFAddrs::new() {
+        for mut iface in ifaddrs {
             //println!("{:?} ({})", iface, iface.flags & (libc::IFF_RUNNING as u32));
             if iface.flags & (libc::IFF_RUNNING as u32) != 0 {
                 if iface.is_lan() {
@@ -255,91 +303,172 @@ impl Interfaces {
                 }
             }
         }
+        Ok(())
     }
+}
 
-    pub fn status_vpn(&mut self) -> Option<String> {
-        self.update();
-        match &self.vpn {
-            None => {
-                if self.show_vpn_down {
-                    Some(BAD.to_string() + ICON_VPN + " down")
-                } else {
-                    None
-                }
-            },
-            Some(ifaddr) => Some((GOOD.to_string() + ICON_VPN + " " + &ifaddr.addr) + &speed_to_str(ifaddr.rx_speed, ICON_DOWN) + &speed_to_str(ifaddr.tx_speed, ICON_UP)),
-        }
+impl Network {
+    pub fn new(kind: Kind, config: &BlockConfig) -> Self {
+        let mut result = Self {
+            kind,
+            interfaces: Interfaces::new(config.root.clone()),
+            netlink: -1,
+            wlan_info: None,
+            show_down: false,
+            quality_warn: QUALITY_WARN,
+            format: Format::parse(FORMAT).unwrap(),
+            format_down: Format::parse(FORMAT_DOWN).unwrap(),
+        };
+        result.configure(config);
+        result
     }
 
-    pub fn status_wlan(&mut self) -> Option<String> {
-        self.update();
-        match &self.wlan {
-            Some(ifaddr) => {
-                let mut first = "";
-                let info = wlaninfo::WLanInfo::new(&ifaddr.name);
-
-                let mut result = match info.quality {
-                    Some(quality) => if quality < 50.0 { WARN } else { GOOD },
-                    None => GOOD,
-                }.to_string() + ICON_WLAN + " " + &ifaddr.addr + " (";
-
-                match info.essid {
-                    Some(essid) => {
-                        result = result + first + &essid;
-                        first = " ";
-                    },
-                    None => (),
-                };
+    fn configure(&mut self, config: &BlockConfig) {
+        let show_down = match self.kind {
+            Kind::Lan => true,
+            Kind::Wlan | Kind::Vpn => false,
+        };
+        let format = match self.kind {
+            Kind::Wlan => FORMAT_WLAN,
+            Kind::Lan | Kind::Vpn => FORMAT,
+        };
+        self.show_down = config.get_bool("show_down", show_down);
+        self.quality_warn = config.get_f64("quality_warn", QUALITY_WARN);
+        self.format = config.get_format("format", format);
+        self.format_down = config.get_format("format_down", FORMAT_DOWN);
+    }
 
-                match info.quality {
-                    Some(quality) => {
-                        result = result + first + &format!("{:0.0}%", quality);
-                        //first = " ";
-                    },
-                    None => (),
-                };
+    // Shown when the interface is down, if at all
+    fn status_down(&self, icon: &str) -> Option<Text> {
+        if self.show_down {
+            let mut values = Values::new();
+            values.insert("icon", icon.to_string());
+            Some(Text::new(Severity::Bad, self.format_down.render(&values)))
+        } else {
+            None
+        }
+    }
 
-                /*
-                match info.frequency {
-                    Some(freq) => {
-                        result = result + first + "freq " + &prefixed(freq) + "Hz";
-                        first = " ";
-                    },
-                    None => (),
-                };
-                */
-                /*
-                match info.bitrate {
-                    Some(bitrate) => {
-                        result = result + first + &prefixed(bitrate) + "b/s";
-                        //first = " ";
-                    },
-                    None => (),
+    fn status_vpn(&self) -> Option<Text> {
+        match &self.interfaces.vpn {
+            None => self.status_down(ICON_VPN),
+            Some(ifaddr) => Some(Text::new(Severity::Good, self.format.render(&ifaddr_values(ifaddr, ICON_VPN)))),
+        }
+    }
+
+    fn status_wlan(&self) -> Option<Text> {
+        match (&self.interfaces.wlan, &self.wlan_info) {
+            (Some(ifaddr), Some(info)) => {
+                let severity = match info.quality {
+                    Some(quality) => if quality < self.quality_warn { Severity::Warn } else { Severity::Good },
+                    None => Severity::Good,
                 };
-                */
-                Some(result + ")" + &speed_to_str(ifaddr.rx_speed, ICON_DOWN) + &speed_to_str(ifaddr.tx_speed, ICON_UP))
-            },
-            None => {
-                if self.show_wlan_down {
-                    Some(BAD.to_string() + ICON_WLAN + " down")
-                } else {
-                    None
+                let mut values = ifaddr_values(ifaddr, ICON_WLAN);
+                if let Some(essid) = &info.essid {
+                    values.insert("ssid", essid.clone());
+                }
+                if let Some(quality) = info.quality {
+                    values.insert("quality", format!("{:0.0}", quality));
+                }
+                if let Some(freq) = info.frequency {
+                    values.insert("frequency", format!("{:0.3}GHz", freq / 1e9));
                 }
+                if let Some(bitrate) = info.bitrate {
+                    values.insert("bitrate", format!("{:0.1}Mb/s", bitrate / 1e6));
+                }
+                Some(Text::new(severity, self.format.render(&values)))
             },
+            _ => self.status_down(ICON_WLAN),
         }
     }
 
-    pub fn status_lan(&mut self) -> Option<String> {
-        self.update();
-        match &self.lan {
-            None => {
-                if self.show_lan_down {
-                    Some(BAD.to_string() + ICON_LAN + " down")
-                } else {
-                    None
-                }
+    fn status_lan(&self) -> Option<Text> {
+        match &self.interfaces.lan {
+            None => self.status_down(ICON_LAN),
+            Some(ifaddr) => Some(Text::new(Severity::Good, self.format.render(&ifaddr_values(ifaddr, ICON_LAN)))),
+        }
+    }
+}
+
+// Listen for link and address changes, so the block can update as soon as
+// an interface comes or goes.
+fn netlink_socket() -> Result<RawFd> {
+    let fd = os_check!(socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE))?;
+    let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    addr.nl_groups = (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+    let addr_p = &addr as *const libc::sockaddr_nl as *const libc::sockaddr;
+    if let Err(err) = os_check!(bind(fd, addr_p, mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t)) {
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    Ok(fd)
+}
+
+impl Drop for Network {
+    fn drop(&mut self) {
+        if self.netlink >= 0 {
+            unsafe { libc::close(self.netlink) };
+        }
+    }
+}
+
+impl Block for Network {
+    fn init(&mut self) {
+        // without it, changes only show up every period
+        self.netlink = match netlink_socket() {
+            Ok(fd) => fd,
+            Err(err) => {
+                eprintln!("dwmstatus: netlink: {}", err);
+                -1
             },
-            Some(ifaddr) => Some(GOOD.to_string() + ICON_LAN + " " + &ifaddr.addr + &speed_to_str(ifaddr.rx_speed, ICON_DOWN) + &speed_to_str(ifaddr.tx_speed, ICON_UP)),
+        };
+    }
+
+    fn update(&mut self) -> Result<()> {
+        // the messages themselves don't matter, everything is re-read below
+        let mut buf = [0u8; 4096];
+        while self.netlink >= 0 && unsafe { recv(self.netlink, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) } > 0 {}
+
+        self.interfaces.update()?;
+        if let Kind::Wlan = self.kind {
+            self.wlan_info = match &self.interfaces.wlan {
+                Some(ifaddr) => Some(wlaninfo::WLanInfo::new(&ifaddr.name)?),
+                None => None,
+            };
         }
+        Ok(())
+    }
+
+    fn render(&self) -> Option<Text> {
+        match self.kind {
+            Kind::Lan => self.status_lan(),
+            Kind::Wlan => self.status_wlan(),
+            Kind::Vpn => self.status_vpn(),
+        }
+    }
+
+    fn event_fd(&self) -> Option<RawFd> {
+        if self.netlink >= 0 { Some(self.netlink) } else { None }
+    }
+
+    fn describe(&self) -> Option<String> {
+        let ifaddr = match self.kind {
+            Kind::Lan => &self.interfaces.lan,
+            Kind::Wlan => &self.interfaces.wlan,
+            Kind::Vpn => &self.interfaces.vpn,
+        };
+        Some(ifaddr.as_ref().map_or("no interface".to_string(), |ifaddr| ifaddr.name.clone()))
+    }
+
+    // the bytes counted before the suspend weren't sent over the time since
+    fn resume(&mut self) {
+        self.interfaces.reset();
+    }
+
+    // keep the byte counters, so the speeds carry on
+    fn reconfigure(&mut self, config: &BlockConfig) -> bool {
+        self.configure(config);
+        true
     }
 }
@@ -1,13 +1,44 @@
-use crate::prelude::*;
+use crate::block::Block;
+use crate::config::BlockConfig;
+use crate::error::Result;
+use crate::format::{Format, Values};
+use crate::markup::{Severity, Text};
 use libc::{getloadavg, c_double};
 
-pub fn status() -> String {
-    let mut result = "".to_string();
-    let mut avgs: Vec<c_double> = vec![0.0; 3];
-    perror_check!(getloadavg(avgs.as_mut_ptr(), 3));
-    if avgs[0] >= 5.0 {
-        result += BAD;
+static FORMAT: &str = "{1m} {5m} {15m}";
+static BAD_LOAD: f64 = 5.0;
+
+pub struct Load {
+    format: Format,
+    bad: f64,
+    avgs: [c_double; 3],
+}
+
+impl Load {
+    pub fn new(config: &BlockConfig) -> Self {
+        Self { format: config.get_format("format", FORMAT), bad: config.get_f64("bad", BAD_LOAD), avgs: [0.0; 3] }
+    }
+}
+
+impl Block for Load {
+    fn update(&mut self) -> Result<()> {
+        let avgs = &mut self.avgs;
+        os_check!(getloadavg(avgs.as_mut_ptr(), 3))?;
+        Ok(())
+    }
+
+    // what the load is relative to
+    fn describe(&self) -> Option<String> {
+        let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+        Some(format!("{} CPU{}", cpus, if cpus == 1 { "" } else { "s" }))
+    }
+
+    fn render(&self) -> Option<Text> {
+        let severity = if self.avgs[0] >= self.bad { Severity::Bad } else { Severity::Normal };
+        let mut values = Values::new();
+        values.insert("1m", format!("{:0.2}", self.avgs[0]));
+        values.insert("5m", format!("{:0.2}", self.avgs[1]));
+        values.insert("15m", format!("{:0.2}", self.avgs[2]));
+        Some(Text::new(severity, self.format.render(&values)))
     }
-    result += &format!("{:0.2} {:0.2} {:0.2}", avgs[0], avgs[1], avgs[2]);
-    result
 }
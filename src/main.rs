@@ -1,48 +1,274 @@
-use chrono;
 mod prelude;
 mod battery;
+mod block;
+mod clock;
+mod config;
+mod control;
 mod cpu;
 mod disk;
+mod error;
+mod format;
 mod ifaddrs;
 mod load;
+mod mainloop;
+mod markup;
 mod media;
 mod mem;
+mod output;
 mod temp;
+mod threaded;
+mod paloop;
+mod persistent;
 mod pulse;
+mod root;
+mod script;
 mod wlaninfo;
 
-use std::{ffi, ptr, time};
-use chrono::prelude::*;
+use serde::Serialize;
+use std::os::unix::io::RawFd;
+use std::{env, ffi, path, process, ptr, time};
+use crate::block::Block;
+use crate::config::{BlockConfig, Colors};
+use crate::control::{Command, Control};
+use crate::mainloop::{EventLoop, Token};
+use crate::markup::{Markup, Severity, Text};
+use crate::output::{Output, Segment};
 use crate::prelude::*;
+use crate::root::Root;
 use x11::xlib;
 
 static ALLOW_EARLY: u64 = 1;  // seconds to allow Fields to update early
-static SEP: &str = " │ ";
-
-type Data = (pulse::Pulse, temp::Temp, ifaddrs::Interfaces);
+static MAX_BACKOFF: time::Duration = time::Duration::from_secs(300);  // between retries of a failing Field
+static ONCE_GRACE: time::Duration = time::Duration::from_millis(500);  // for blocks updating in the background with --once
+static MIN_SUSPEND: time::Duration = time::Duration::from_secs(1);  // less isn't worth refreshing everything for
 
 struct Field {
-    updater: Box<dyn Fn(Option<&mut Data>) -> Option<String>>,
-    value: Option<String>,
-    period: time::Duration,
+    block: Box<dyn Block>,
+    config: BlockConfig,
+    value: Option<Text>,
     next: time::Instant,
+    // consecutive failed updates, and the last error logged
+    failures: u32,
+    error: Option<String>,
+    // hidden through the control socket
+    hidden: bool,
+    // `value` changed since the bar was last written
+    changed: bool,
+    // config.align(), read once
+    align: bool,
 }
 
 impl Field {
-    fn new(updater: Box<dyn Fn(Option<&mut Data>) -> Option<String>>, period: time::Duration) -> Self {
-        Self { updater: updater, value: None, period: period, next: time::Instant::now() }
+    fn new(block: Box<dyn Block>, config: BlockConfig) -> Self {
+        let align = config.align();
+        Self { block, config, value: None, next: time::Instant::now(), failures: 0, error: None, hidden: false, changed: true, align }
+    }
+
+    fn update(&mut self, now: time::Instant) {
+        match self.block.update() {
+            Ok(()) => {
+                self.failures = 0;
+                self.error = None;
+                self.set_value(self.block.render());
+                self.next = if self.align {
+                    // from when the clocks are read, not from `now`, so
+                    // never before the boundary
+                    time::Instant::now() + until_aligned(self.config.period(), &chrono::Local::now())
+                } else {
+                    now + self.config.period()
+                };
+            },
+            Err(err) => {
+                // log each new error once, not on every retry
+                let err = err.to_string();
+                if self.error.as_ref() != Some(&err) {
+                    eprintln!("dwmstatus: block {}: {}", self.config.name, err);
+                }
+                self.error = Some(err);
+                self.failures += 1;
+                self.set_value(Some(Text::new(Severity::Bad, format!("{} err", self.config.name))));
+                self.next = now + backoff(self.config.period(), self.failures);
+            },
+        }
     }
+
+    fn set_value(&mut self, value: Option<Text>) {
+        if value != self.value {
+            self.value = value;
+            self.changed = true;
+        }
+    }
+}
+
+// how long from `now` to the next multiple of `period` in local time, eg.
+// the top of the next minute for 60s
+fn until_aligned<Tz: chrono::TimeZone>(period: time::Duration, now: &chrono::DateTime<Tz>) -> time::Duration {
+    let period = period.as_nanos() as i128;
+    if period == 0 {
+        return time::Duration::ZERO;
+    }
+    let offset = chrono::Offset::fix(now.offset()).local_minus_utc() as i128;
+    let local = (now.timestamp() as i128 + offset) * 1_000_000_000 + now.timestamp_subsec_nanos() as i128;
+    time::Duration::from_nanos((period - local.rem_euclid(period)) as u64)
+}
+
+// How long the system has spent suspended since boot: CLOCK_BOOTTIME counts
+// it, CLOCK_MONOTONIC doesn't
+fn suspended() -> time::Duration {
+    let read = |clock| {
+        let mut spec = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe { libc::clock_gettime(clock, &mut spec) };
+        time::Duration::new(spec.tv_sec as u64, spec.tv_nsec as u32)
+    };
+    read(libc::CLOCK_BOOTTIME).saturating_sub(read(libc::CLOCK_MONOTONIC))
+}
+
+// how long to wait before retrying a Field that failed `failures` times in a
+// row: the period, doubling each time up to MAX_BACKOFF
+fn backoff(period: time::Duration, failures: u32) -> time::Duration {
+    let limit = MAX_BACKOFF.max(period);
+    period.checked_mul(1 << (failures - 1).min(16)).map_or(limit, |delay| delay.min(limit))
 }
 
 struct Status {
     fields : Vec<Field>,
     idx_by_time : Vec<usize>,
-    data: Option<Data>,
+    events: EventLoop,
+    output: Box<dyn Output>,
+    control: Option<Control>,
+    children: Vec<process::Child>,
+    // where to reload the configuration from, as given on the command line
+    config_path: Option<path::PathBuf>,
+    config_watch: Option<config::Watch>,
+    reload_pending: bool,
+    // --root, kept for reloads
+    root: Option<Root>,
+    // when the bar first differed from what was last written, and how long
+    // to wait from then for more changes before writing it
+    dirty: Option<time::Instant>,
+    coalesce: time::Duration,
+    // suspended() when last checked
+    suspended: time::Duration,
+}
+
+// the visible fields
+fn segments(fields: &[Field]) -> Vec<Segment<'_>> {
+    fields.iter().enumerate().filter(|(_, field)| !field.hidden).filter_map(|(idx, field)| {
+        let marker = field.config.signal().map(|signal| (signal - libc::SIGRTMIN()) as u8);
+        // clicking a block with a signal updates it, whatever the button
+        let buttons = if field.config.on_click.is_some() || marker.is_some() { block::BUTTONS } else { field.block.buttons() };
+        field.value.as_ref().map(|text| Segment { name: &field.config.name, instance: idx, marker, buttons, text })
+    }).collect()
+}
+
+// A field, as dumped to the control socket
+#[derive(Serialize)]
+struct FieldDump<'a> {
+    name: &'a str,
+    instance: usize,
+    text: Option<&'a str>,
+    severity: Option<Severity>,
+    hidden: bool,
+    error: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Dump<'a> {
+    bar: String,
+    fields: Vec<FieldDump<'a>>,
 }
 
 impl Status {
-    fn new(data: Option<Data>) -> Self {
-        Self { fields: vec![], idx_by_time: vec![], data: data }
+    fn new(output: Box<dyn Output>) -> Self {
+        let mut events = EventLoop::new();
+        // SIGRTMIN+n refreshes the blocks configured with `signal = n`.
+        // Watch all of them, so that stray signals don't kill the bar.
+        let mut signals: Vec<libc::c_int> = (libc::SIGRTMIN()..=libc::SIGRTMAX()).collect();
+        signals.push(libc::SIGUSR1);
+        signals.push(libc::SIGHUP);
+        events.watch_signals(&signals);
+        if let Some(fd) = output.input_fd() {
+            events.watch(fd, Token::Input);
+        }
+        Self { fields: vec![], idx_by_time: vec![], events, output, control: None, children: vec![], config_path: None, config_watch: None, reload_pending: false, root: None, dirty: None, coalesce: time::Duration::ZERO, suspended: suspended() }
+    }
+
+    // Listen for commands on `path`
+    fn listen(&mut self, path: &path::Path) {
+        match Control::bind(path) {
+            Ok(control) => {
+                self.events.watch(control.fd(), Token::Control);
+                self.control = Some(control);
+            },
+            Err(err) => eprintln!("dwmstatus: {}: {}", path.display(), err),
+        }
+    }
+
+    // Reload the configuration on SIGHUP, and whenever the file at `path` (or
+    // the default location) is written
+    fn watch_config(&mut self, path: Option<&path::Path>) {
+        self.config_path = path.map(|path| path.to_path_buf());
+        if let Some(path) = config::Config::path(path) {
+            match config::Watch::new(&path) {
+                Ok(watch) => {
+                    self.events.watch(watch.fd(), Token::Config);
+                    self.config_watch = Some(watch);
+                },
+                // eg. no ~/.config/dwmstatus, SIGHUP still works
+                Err(err) => eprintln!("dwmstatus: not watching {}: {}", path.display(), err),
+            }
+        }
+    }
+
+    fn reload(&mut self) {
+        let config = config::Config::load(self.config_path.as_deref()).map(|mut config| {
+            if let Some(root) = &self.root {
+                config.set_root(root);
+            }
+            config
+        });
+        match config.and_then(|config| self.load(&config.blocks).map(|()| config)) {
+            Ok(config) => {
+                self.coalesce = config.coalesce();
+                eprintln!("dwmstatus: configuration reloaded");
+            },
+            Err(err) => eprintln!("dwmstatus: not reloading: {}", err),
+        }
+    }
+
+    // Replace the fields with `configs`.  Blocks that are configured again
+    // under the same name keep their state where they can, so eg. network
+    // speeds carry on.  On error, nothing changes.
+    fn load(&mut self, configs: &[BlockConfig]) -> Result<(), String> {
+        let mut blocks = configs.iter().map(block::from_config).collect::<Result<Vec<_>, _>>()?;
+        let now = time::Instant::now();
+        for field in &self.fields {
+            if let Some(fd) = field.block.event_fd() {
+                self.events.unwatch(fd);
+            }
+        }
+        let mut old: Vec<Option<Field>> = self.fields.drain(..).map(Some).collect();
+        self.idx_by_time.clear();
+        for (config, block) in configs.iter().zip(blocks.drain(..)) {
+            let same_name = old.iter_mut().find(|field| matches!(field, Some(field) if field.config.name == config.name));
+            match same_name.and_then(|field| field.take()) {
+                Some(mut field) => {
+                    if block::reconfigure(&mut field.block, &field.config, config) {
+                        field.config = config.clone();
+                        field.align = config.align();
+                        field.next = now;
+                        self.add_field(field);
+                    } else {
+                        self.add(block, config);
+                        self.fields.last_mut().unwrap().hidden = field.hidden;
+                    }
+                },
+                None => self.add(block, config),
+            }
+        }
+        // fields may have gone, or moved
+        self.dirty.get_or_insert(now);
+        Ok(())
     }
 
     // ordering of tasks is by task.next. the 0th element has changed it's `next`,
@@ -57,8 +283,23 @@ impl Status {
         }
     }
 
-    fn add(&mut self, updater: Box<dyn Fn(Option<&mut Data>) -> Option<String>>, period: time::Duration) {
-        let new_task = Field::new(updater, period);
+    // a field's `next` has changed out of order, so move it to its new place
+    fn reschedule(&mut self, idx: usize, next: time::Instant) {
+        self.fields[idx].next = next;
+        self.idx_by_time.retain(|&i| i != idx);
+        let pos = self.idx_by_time.iter().position(|&i| next < self.fields[i].next).unwrap_or(self.idx_by_time.len());
+        self.idx_by_time.insert(pos, idx);
+    }
+
+    fn add(&mut self, mut block: Box<dyn Block>, config: &BlockConfig) {
+        block.init();
+        self.add_field(Field::new(block, config.clone()));
+    }
+
+    fn add_field(&mut self, new_task: Field) {
+        if let Some(fd) = new_task.block.event_fd() {
+            self.events.watch(fd, Token::Block(self.fields.len()));
+        }
         for i in 0..self.idx_by_time.len() {
             if new_task.next < self.fields[self.idx_by_time[i]].next {
                 self.idx_by_time.insert(i, self.fields.len());
@@ -73,184 +314,693 @@ impl Status {
     fn run(&mut self) {
         if self.fields.len() > 0 {
             let now = time::Instant::now();
-            while self.fields[self.idx_by_time[0]].next <= (now + time::Duration::new(ALLOW_EARLY, 0)) {
-                let task = &mut self.fields[self.idx_by_time[0]];
-                let updater = &mut task.updater;
-                task.value = updater(self.data.as_mut());
-                task.next = now + task.period;
+            loop {
+                // aligned fields would show eg. the minute before, and one
+                // with a short period would be due again straight away
+                let field = &self.fields[self.idx_by_time[0]];
+                let early = if field.align { time::Duration::ZERO } else { time::Duration::new(ALLOW_EARLY, 0).min(field.config.period() / 2) };
+                if field.next > now + early {
+                    break;
+                }
+                self.fields[self.idx_by_time[0]].update(now);
                 self.reorder_first();
             }
         }
     }
 
-    fn sleep(&mut self) {
-        match self.next_update() {
-            Some(duration) => {
-                // use PulseAudio's mainloop poll to sleep
-                // so callbacks are handled on time
-                self.data.as_mut().unwrap().0.sleep(duration);
-                //thread::sleep(duration);
-            },
-            None => (),
-        }
-    }
-
-    fn next_update(&self) -> Option<time::Duration> {
+    // Update every field once, then give the blocks that update in the
+    // background (threaded ones, volume) `grace` to come back
+    fn once(&mut self, grace: time::Duration) {
         let now = time::Instant::now();
-        if self.fields[self.idx_by_time[0]].next <= now {
-            return None;
+        for field in &mut self.fields {
+            field.update(now);
+        }
+        if self.fields.iter().all(|field| field.block.event_fd().is_none()) {
+            return;
+        }
+        let deadline = now + grace;
+        while let Some(left) = deadline.checked_duration_since(time::Instant::now()).filter(|left| !left.is_zero()) {
+            for token in self.events.wait(left) {
+                if let Token::Block(idx) = token {
+                    self.fields[idx].update(time::Instant::now());
+                }
+            }
         }
-        return Some(self.fields[self.idx_by_time[0]].next - now);
     }
 
-    fn print(&self) {
-        let mut first = true;
-        for t in &self.fields[..] {
-            match &t.value {
-                Some(value) => {
-                    if first {
-                        first = false;
-                    } else {
-                        print!("{}{}", NORM, SEP);
+    // sleep until the next field is due, or until one of the blocks' event
+    // file descriptors becomes readable, in which case that field is due now
+    fn sleep(&mut self) {
+        let duration = match self.next_update() {
+            Some(duration) => duration,
+            None => { return; },
+        };
+        // a write held back for more changes may be due first
+        let duration = match self.dirty {
+            Some(since) => duration.min((since + self.coalesce).saturating_duration_since(time::Instant::now())),
+            None => duration,
+        };
+        let now = time::Instant::now();
+        for token in self.events.wait(duration) {
+            match token {
+                Token::Timer => (),
+                Token::Signal => {
+                    for info in self.events.read_signals() {
+                        if info.ssi_signo as libc::c_int == libc::SIGHUP {
+                            self.reload_pending = true;
+                            continue;
+                        }
+                        // dwm's statuscmd patch sends the mouse button along with the signal
+                        let button = if info.ssi_code == libc::SI_QUEUE && info.ssi_int > 0 { Some(info.ssi_int as u8) } else { None };
+                        self.on_signal(info.ssi_signo as libc::c_int, button, now);
                     }
-                    print!("{}", value);
                 },
-                None => (),
+                Token::Input => {
+                    let fd = self.output.input_fd();
+                    for (idx, button) in self.output.clicks() {
+                        if idx < self.fields.len() {
+                            self.click(idx, button);
+                            self.reschedule(idx, now);
+                        }
+                    }
+                    // the bar closed its end, don't spin on EOF
+                    if let (Some(fd), None) = (fd, self.output.input_fd()) {
+                        self.events.unwatch(fd);
+                    }
+                },
+                Token::Config => {
+                    if self.config_watch.as_ref().is_some_and(|watch| watch.changed()) {
+                        self.reload_pending = true;
+                    }
+                },
+                Token::Control => {
+                    if let Some(control) = &mut self.control {
+                        for fd in control.accept() {
+                            self.events.watch(fd, Token::Client(fd));
+                        }
+                    }
+                },
+                Token::Client(fd) => self.on_client(fd, now),
+                // aligned fields are due at other instants now
+                Token::Clock => {
+                    for idx in 0..self.fields.len() {
+                        if self.fields[idx].align {
+                            self.reschedule(idx, now);
+                        }
+                    }
+                },
+                Token::Block(idx) => self.reschedule(idx, now),
             }
         }
-        println!("");
-    }
-
-    fn xsetroot(&self, dpy: *mut xlib::Display) {
-        let mut first = true;
-        let result = ffi::CString::new({
-            let mut result = "".to_string();
-            for t in &self.fields[..] {
-                match &t.value {
-                    Some(value) => {
-                        if first {
-                            first = false;
-                        } else {
-                            result += NORM;
-                            result += SEP;
-                        }
-                        result += &value;
-                    },
-                    None => (),
+        // resuming wakes us up through Token::Clock
+        let suspended = suspended();
+        if suspended >= self.suspended + MIN_SUSPEND {
+            self.resume(now);
+        }
+        self.suspended = suspended;
+        // after the other tokens, which refer to the fields as they were
+        if self.reload_pending {
+            self.reload_pending = false;
+            self.reload();
+        }
+        // reap finished on_click commands
+        self.children.retain_mut(|child| !matches!(child.try_wait(), Ok(Some(_)) | Err(_)));
+    }
+
+    // SIGUSR1 refreshes everything, real-time signals the matching blocks,
+    // or clicks them if a button was given
+    fn on_signal(&mut self, signal: libc::c_int, button: Option<u8>, now: time::Instant) {
+        for idx in 0..self.fields.len() {
+            if signal == libc::SIGUSR1 || self.fields[idx].config.signal() == Some(signal) {
+                if let (Some(button), false) = (button, signal == libc::SIGUSR1) {
+                    self.click(idx, button);
                 }
+                self.reschedule(idx, now);
             }
-            result
-        }).unwrap();
+        }
+    }
 
-        unsafe {
-            xlib::XStoreName(dpy, xlib::XDefaultRootWindow(dpy), result.as_ptr());
-            xlib::XSync(dpy, false as i32);
+    // After a suspend, every field is out of date
+    fn resume(&mut self, now: time::Instant) {
+        for idx in 0..self.fields.len() {
+            self.fields[idx].block.resume();
+            self.reschedule(idx, now);
         }
     }
-}
 
-fn status_volume(data: Option<&mut Data>) -> Option<String> {
-    data.unwrap().0.status()
-}
+    fn on_client(&mut self, fd: RawFd, now: time::Instant) {
+        let lines = match self.control.as_mut().and_then(|control| control.read(fd)) {
+            Some(lines) => lines,
+            None => {
+                self.events.unwatch(fd);
+                if let Some(control) = &mut self.control {
+                    control.close(fd);
+                }
+                return;
+            },
+        };
+        for line in lines {
+            let reply = match Command::parse(&line) {
+                Ok(command) => self.command(command, now),
+                Err(err) => Err(err),
+            };
+            let reply = reply.unwrap_or_else(|err| format!("error: {}", err));
+            if let Some(control) = &mut self.control {
+                control.reply(fd, &reply);
+            }
+        }
+    }
 
-fn status_load(_data: Option<&mut Data>) -> Option<String> {
-    Some(load::status())
-}
+    // Carry out a command from the control socket, returning the reply
+    fn command(&mut self, command: Command, now: time::Instant) -> Result<String, String> {
+        let name = match &command {
+            Command::Dump => return Ok(self.dump()),
+            Command::Refresh(None) => {
+                for idx in 0..self.fields.len() {
+                    self.reschedule(idx, now);
+                }
+                return Ok("ok".to_string());
+            },
+            Command::Refresh(Some(name)) | Command::Hide(name) | Command::Show(name) | Command::Action(name, _) | Command::Describe(name) => name,
+        };
+        let matching: Vec<usize> = (0..self.fields.len()).filter(|&idx| &self.fields[idx].config.name == name).collect();
+        if matching.is_empty() {
+            return Err(format!("no block {}", name));
+        }
+        if let Command::Describe(_) = command {
+            let descriptions: Vec<String> = matching.iter().filter_map(|&idx| self.fields[idx].block.describe()).collect();
+            return Ok(if descriptions.is_empty() { "nothing to describe".to_string() } else { descriptions.join("; ") });
+        }
+        for idx in matching {
+            match &command {
+                Command::Hide(_) | Command::Show(_) => {
+                    self.fields[idx].hidden = matches!(command, Command::Hide(_));
+                    self.dirty.get_or_insert(now);
+                },
+                Command::Action(_, action) => {
+                    self.fields[idx].block.action(action).map_err(|err| err.to_string())?;
+                },
+                _ => (),
+            }
+            self.reschedule(idx, now);
+        }
+        Ok("ok".to_string())
+    }
 
-fn status_lan(data: Option<&mut Data>) -> Option<String> {
-    data.unwrap().2.status_lan()
-}
+    fn dump(&self) -> String {
+        let bar = output::markup_line(&segments(&self.fields), Markup::Plain, &Colors::default(), false);
+        let fields = self.fields.iter().enumerate().map(|(idx, field)| FieldDump {
+            name: &field.config.name,
+            instance: idx,
+            text: field.value.as_ref().map(|text| &text.text[..]),
+            severity: field.value.as_ref().map(|text| text.severity),
+            hidden: field.hidden,
+            error: field.error.as_deref(),
+        }).collect();
+        serde_json::to_string(&Dump { bar, fields }).unwrap()
+    }
 
-fn status_vpn(data: Option<&mut Data>) -> Option<String> {
-    data.unwrap().2.status_vpn()
-}
+    fn click(&mut self, idx: usize, button: u8) {
+        let field = &mut self.fields[idx];
+        match &field.config.on_click {
+            Some(cmd) => match shell(cmd).env("BLOCK_BUTTON", button.to_string()).spawn() {
+                Ok(child) => self.children.push(child),
+                Err(err) => eprintln!("dwmstatus: {}: {}", cmd, err),
+            },
+            None => field.block.click(button),
+        }
+    }
 
-fn status_wlan(data: Option<&mut Data>) -> Option<String> {
-    data.unwrap().2.status_wlan()
-}
+    fn next_update(&self) -> Option<time::Duration> {
+        let now = time::Instant::now();
+        if self.fields[self.idx_by_time[0]].next <= now {
+            return None;
+        }
+        return Some(self.fields[self.idx_by_time[0]].next - now);
+    }
 
-fn status_battery(_data: Option<&mut Data>) -> Option<String> {
-    battery::status()
+    // Write the bar if it has changed, once `coalesce` has passed since it
+    // first did, so eg. a burst of volume changes makes one write
+    fn show(&mut self, now: time::Instant) {
+        for field in &mut self.fields {
+            if std::mem::take(&mut field.changed) {
+                self.dirty.get_or_insert(now);
+            }
+        }
+        if self.dirty.is_some_and(|since| now >= since + self.coalesce) {
+            self.flush();
+        }
+    }
+
+    // Write the bar now, changed or not
+    fn flush(&mut self) {
+        self.dirty = None;
+        let segments = segments(&self.fields);
+        self.output.show(&segments);
+    }
 }
 
-fn status_disk(_data: Option<&mut Data>) -> Option<String> {
-    Some(disk::status())
+struct Args {
+    config: Option<path::PathBuf>,
+    output: Option<String>,
+    markup: Option<Markup>,
+    display: Option<String>,
+    // update and print once, then exit
+    once: bool,
+    // only show this block
+    block: Option<String>,
+    list_blocks: bool,
+    // read /proc and /sys from here, eg. a test fixture
+    root: Option<Root>,
 }
 
-fn status_ram(_data: Option<&mut Data>) -> Option<String> {
-    Some(mem::status())
+fn parse_args() -> Args {
+    let mut result = Args { config: None, output: None, markup: None, display: None, once: false, block: None, list_blocks: false, root: None };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--config" | "-c" => match args.next() {
+                Some(path) => result.config = Some(path::PathBuf::from(path)),
+                None => usage(),
+            },
+            "--output" | "-o" => match args.next() {
+                Some(output) => result.output = Some(output),
+                None => usage(),
+            },
+            "--markup" | "-m" => match args.next().as_deref().and_then(Markup::from_name) {
+                Some(markup) => result.markup = Some(markup),
+                None => usage(),
+            },
+            "--stdout" => result.output = Some("stdout".to_string()),
+            "--display" | "-d" => match args.next() {
+                Some(display) => result.display = Some(display),
+                None => usage(),
+            },
+            "--once" | "-1" => result.once = true,
+            "--block" | "-b" => match args.next() {
+                Some(name) => result.block = Some(name),
+                None => usage(),
+            },
+            "--list-blocks" | "-l" => result.list_blocks = true,
+            "--root" => match args.next() {
+                Some(dir) => result.root = Some(Root::new(dir)),
+                None => usage(),
+            },
+            _ => usage(),
+        }
+    }
+    result
 }
 
-fn status_cpuspeed(_data: Option<&mut Data>) -> Option<String> {
-    Some(cpu::status())
+fn usage() -> ! {
+    eprintln!("usage: dwmstatus [--config PATH] [--output x|stdout|i3bar|lemonbar] [--stdout]\n                 [--markup statuscolors|status2d|ansi|plain] [--display DISPLAY]\n                 [--once] [--block NAME] [--list-blocks] [--root DIR]");
+    process::exit(2);
 }
 
-fn status_temp(data: Option<&mut Data>) -> Option<String> {
-    Some(data.unwrap().1.status())
+fn open_output(name: Option<&str>, markup: Markup, display: Option<&str>, config: &config::Config) -> Box<dyn Output> {
+    let colors = config.colors.clone();
+    match name {
+        None | Some("x") => {
+            let display_c = display.map(|display| ffi::CString::new(display).unwrap_or_else(|_| usage()));
+            let display = unsafe { xlib::XOpenDisplay(display_c.as_ref().map_or(ptr::null(), |display| display.as_ptr())) };
+            if display.is_null() {
+                eprintln!("dwmstatus: cannot open display.");
+                Box::new(output::Stdout::new(markup, colors))
+            } else {
+                Box::new(output::XRoot::new(display, config.statuscmd, markup, colors))
+            }
+        },
+        Some("stdout") => Box::new(output::Stdout::new(markup, colors)),
+        Some("i3bar") => Box::new(output::I3bar::new(colors)),
+        Some("lemonbar") => Box::new(output::Lemonbar::new(colors)),
+        Some(_) => usage(),
+    }
 }
 
-fn status_time(_data: Option<&mut Data>) -> Option<String> {
-    Some(Local::now().format("%Y-%m-%d %H:%M").to_string())
+// Every block, with the hardware it finds, configured as in `config` where it is
+fn list_blocks(config: &config::Config, root: &Root) {
+    for name in block::NAMES {
+        let block_config = config.blocks.iter().find(|block| block.name == *name).cloned().unwrap_or_else(|| {
+            let mut block = BlockConfig::named(name);
+            block.root = root.clone();
+            block
+        });
+        let mut block = match block::from_config(&block_config) {
+            Ok(block) => block,
+            Err(err) => {
+                println!("{:<8} {}", name, err);
+                continue;
+            },
+        };
+        block.init();
+        let error = settle(&mut *block, ONCE_GRACE).err().map(|err| format!(" (error: {})", err));
+        println!("{:<8} {}{}", name, block.describe().unwrap_or_default(), error.unwrap_or_default());
+    }
 }
 
-fn status_media(_data: Option<&mut Data>) -> Option<String> {
-    media::status()
+// Update `block`, and give one that gets there in the background (threaded,
+// or waiting on a server) until `grace` to come up with a value or an error
+fn settle(block: &mut dyn Block, grace: time::Duration) -> error::Result<()> {
+    let deadline = time::Instant::now() + grace;
+    block.update()?;
+    let fd = match block.event_fd() {
+        Some(fd) => fd,
+        None => { return Ok(()); },
+    };
+    while block.render().is_none() {
+        let left = match deadline.checked_duration_since(time::Instant::now()) {
+            Some(left) => left,
+            None => break,
+        };
+        let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut pollfd, 1, left.as_millis() as libc::c_int) } <= 0 {
+            break;
+        }
+        block.update()?;
+    }
+    Ok(())
 }
 
 fn main() {
-    let data = (pulse::Pulse::new(), temp::Temp::new(), ifaddrs::Interfaces::new(true, false, false));
-    let mut status = Status::new(Some(data));
-    status.add(Box::new(status_media), time::Duration::new(15, 0));
-    status.add(Box::new(status_vpn), time::Duration::new(5, 0));
-    status.add(Box::new(status_wlan), time::Duration::new(5, 0));
-    status.add(Box::new(status_lan), time::Duration::new(5, 0));
-    status.add(Box::new(status_battery), time::Duration::new(15, 0));
-    status.add(Box::new(status_disk), time::Duration::new(15, 0));
-    status.add(Box::new(status_ram), time::Duration::new(15, 0));
-    status.add(Box::new(status_cpuspeed), time::Duration::new(15, 0));
-    status.add(Box::new(status_load), time::Duration::new(5, 0));
-    status.add(Box::new(status_temp), time::Duration::new(5, 0));
-    status.add(Box::new(status_volume), time::Duration::new(2, 0));
-    status.add(Box::new(status_time), time::Duration::new(5, 0));
-
-    let display = unsafe { xlib::XOpenDisplay(ptr::null_mut()) };
-    if display.is_null() {
-        eprintln!("dwmstatus: cannot open display.");
+    let args = parse_args();
+    let mut config = match config::Config::load(args.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("dwmstatus: {}", err);
+            process::exit(1);
+        },
+    };
+    if let Some(root) = &args.root {
+        config.set_root(root);
+    }
+    if args.list_blocks {
+        list_blocks(&config, &args.root.clone().unwrap_or_default());
+        return;
+    }
+    if let Some(name) = &args.block {
+        config.blocks.retain(|block| block.name == *name);
+        if config.blocks.is_empty() {
+            let mut block = BlockConfig::named(name);
+            block.root = args.root.clone().unwrap_or_default();
+            config.blocks.push(block);
+        }
+    }
+
+    // one-off and debugging runs print to the terminal
+    let output = args.output.as_deref().or(if args.once || args.block.is_some() { Some("stdout") } else { None });
+    let mut status = Status::new(open_output(output, args.markup.unwrap_or(config.markup), args.display.as_deref(), &config));
+    status.root = args.root.clone();
+    status.coalesce = config.coalesce();
+    if let Err(err) = status.load(&config.blocks) {
+        eprintln!("dwmstatus: {}", err);
+        process::exit(1);
+    }
+    if args.once {
+        status.once(ONCE_GRACE);
+        status.flush();
+        return;
+    }
+    // leave reloading and the socket to the real bar
+    if args.block.is_none() {
+        status.watch_config(args.config.as_deref());
+        match control::default_path() {
+            Some(path) => status.listen(&path),
+            None => eprintln!("dwmstatus: XDG_RUNTIME_DIR isn't set, no control socket"),
+        }
     }
 
     loop {
         status.run();
-        if display.is_null() {
-            status.print();
-        } else {
-            status.xsetroot(display);
-        }
+        status.show(time::Instant::now());
         status.sleep();
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::time;
-    use super::Status;
+    use std::{cell, rc, time};
+    use super::{backoff, settle, until_aligned, Command, Status};
+    use crate::block::Block;
+    use crate::config::{BlockConfig, Colors, Config};
+    use crate::error::{Error, Result};
+    use crate::markup::{Markup, Severity, Text};
+    use crate::output;
+    use crate::root;
+    use crate::threaded::Threaded;
+
+    struct Const(&'static str);
+
+    impl Block for Const {
+        fn update(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn render(&self) -> Option<Text> {
+            Some(Text::normal(self.0))
+        }
+    }
+
+    struct Clicks(rc::Rc<cell::RefCell<Vec<u8>>>);
+
+    impl Block for Clicks {
+        fn update(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn render(&self) -> Option<Text> {
+            None
+        }
+
+        fn click(&mut self, button: u8) {
+            self.0.borrow_mut().push(button);
+        }
+    }
+
+    struct Resumes(rc::Rc<cell::Cell<u32>>);
+
+    impl Block for Resumes {
+        fn update(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn render(&self) -> Option<Text> {
+            None
+        }
+
+        fn resume(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    struct Broken;
+
+    impl Block for Broken {
+        fn update(&mut self) -> Result<()> {
+            Err(Error::Parse("broken".to_string()))
+        }
+
+        fn render(&self) -> Option<Text> {
+            Some(Text::normal("unreachable"))
+        }
+    }
+
+    // the lines written, as plain text
+    struct Recorder(rc::Rc<cell::RefCell<Vec<String>>>);
+
+    impl output::Output for Recorder {
+        fn show(&mut self, segments: &[output::Segment]) {
+            self.0.borrow_mut().push(output::markup_line(segments, Markup::Plain, &Colors::default(), false));
+        }
+    }
+
+    fn config(period: u64, signal: Option<u32>) -> BlockConfig {
+        let signal = signal.map_or("".to_string(), |signal| format!("signal = {}", signal));
+        root::config("test", &format!("period = {}\n{}", period, signal), None)
+    }
 
     #[test]
     fn run_reorder() {
-        let mut status = Status::new(None);
-        //let mut runs : Vec<usize> = vec![];
-        status.add(Box::new(|_: Option<&mut ()>| { "A".to_string() }), time::Duration::new(30, 0));
-        status.add(Box::new(|_: Option<&mut ()>| { "B".to_string() }), time::Duration::new(20, 0));
-        status.add(Box::new(|_: Option<&mut ()>| { "C".to_string() }), time::Duration::new(10, 0));
+        let mut status = Status::new(Box::new(output::Stdout::new(Markup::Plain, Colors::default())));
+        status.add(Box::new(Const("A")), &config(30, None));
+        status.add(Box::new(Const("B")), &config(20, None));
+        status.add(Box::new(Const("C")), &config(10, None));
         assert_eq!(status.idx_by_time, vec![0, 1, 2]);
         status.run();
-        assert_eq!(status.fields[0].value, "A");
-        assert_eq!(status.fields[1].value, "B");
-        assert_eq!(status.fields[2].value, "C");
+        assert_eq!(status.fields[0].value, Some(Text::normal("A")));
+        assert_eq!(status.fields[1].value, Some(Text::normal("B")));
+        assert_eq!(status.fields[2].value, Some(Text::normal("C")));
         assert_eq!(status.idx_by_time, vec![2, 1, 0]);
-        status.add(Box::new(|_: Option<&mut ()>| { "D".to_string() }), time::Duration::new(15, 0));
+        status.add(Box::new(Const("D")), &config(15, None));
         assert_eq!(status.idx_by_time, vec![3, 2, 1, 0]);
         status.run();
-        assert_eq!(status.fields[3].value, "D");
+        assert_eq!(status.fields[3].value, Some(Text::normal("D")));
         assert_eq!(status.idx_by_time, vec![2, 3, 1, 0]);
+        status.reschedule(0, time::Instant::now());
+        assert_eq!(status.idx_by_time, vec![0, 2, 3, 1]);
+    }
+
+    #[test]
+    fn short_period() {
+        let mut status = Status::new(Box::new(output::Stdout::new(Markup::Plain, Colors::default())));
+        status.add(Box::new(Const("A")), &root::config("test", "period = 0.5", None));
+        status.run();
+        assert_eq!(status.fields[0].value, Some(Text::normal("A")));
+    }
+
+    #[test]
+    fn signals() {
+        let mut status = Status::new(Box::new(output::Stdout::new(Markup::Plain, Colors::default())));
+        status.add(Box::new(Const("A")), &config(30, Some(1)));
+        status.add(Box::new(Const("B")), &config(20, Some(2)));
+        status.add(Box::new(Const("C")), &config(10, None));
+        status.run();
+        assert_eq!(status.idx_by_time, vec![2, 1, 0]);
+        status.on_signal(libc::SIGRTMIN() + 1, None, time::Instant::now());
+        assert_eq!(status.idx_by_time, vec![0, 2, 1]);
+        status.run();
+        assert_eq!(status.idx_by_time, vec![2, 1, 0]);
+        status.on_signal(libc::SIGUSR1, None, time::Instant::now());
+        assert!(status.next_update().is_none());
+    }
+
+    #[test]
+    fn clicks() {
+        let clicks = rc::Rc::new(cell::RefCell::new(vec![]));
+        let mut status = Status::new(Box::new(output::Stdout::new(Markup::Plain, Colors::default())));
+        status.add(Box::new(Clicks(clicks.clone())), &config(30, Some(3)));
+        status.run();
+        status.on_signal(libc::SIGRTMIN() + 3, Some(1), time::Instant::now());
+        status.on_signal(libc::SIGRTMIN() + 3, None, time::Instant::now());
+        status.on_signal(libc::SIGRTMIN() + 2, Some(2), time::Instant::now());
+        status.on_signal(libc::SIGUSR1, Some(3), time::Instant::now());
+        assert_eq!(*clicks.borrow(), vec![1]);
+    }
+
+    #[test]
+    fn errors() {
+        let mut status = Status::new(Box::new(output::Stdout::new(Markup::Plain, Colors::default())));
+        status.add(Box::new(Broken), &config(10, None));
+        status.run();
+        assert_eq!(status.fields[0].value, Some(Text::new(Severity::Bad, "test err")));
+        assert_eq!(status.fields[0].failures, 1);
+        let second = time::Duration::from_secs(1);
+        assert_eq!(backoff(10 * second, 1), 10 * second);
+        assert_eq!(backoff(10 * second, 3), 40 * second);
+        assert_eq!(backoff(10 * second, 30), 300 * second);
+        assert_eq!(backoff(600 * second, 2), 600 * second);
+    }
+
+    #[test]
+    fn aligned() {
+        use chrono::TimeZone;
+        let second = time::Duration::from_secs(1);
+        let utc = chrono::Utc.with_ymd_and_hms(2024, 3, 1, 12, 34, 56).unwrap();
+        assert_eq!(until_aligned(60 * second, &utc), 4 * second);
+        assert_eq!(until_aligned(5 * second, &utc), 4 * second);
+        assert_eq!(until_aligned(60 * second, &(utc + chrono::Duration::milliseconds(250))), time::Duration::from_millis(3750));
+        // on the boundary, the next one
+        assert_eq!(until_aligned(60 * second, &(utc + chrono::Duration::seconds(4))), 60 * second);
+        // hours are local ones, 12:34 UTC is 18:04 in India
+        let india = utc.with_timezone(&chrono::FixedOffset::east_opt(5 * 3600 + 1800).unwrap());
+        assert_eq!(until_aligned(3600 * second, &india), (55 * 60 + 4) * second);
+        // not run early, unlike other fields
+        let mut status = Status::new(Box::new(output::Stdout::new(Markup::Plain, Colors::default())));
+        let mut config = config(60, None);
+        config.name = "time".to_string();
+        status.add(Box::new(Const("A")), &config);
+        status.fields[0].next = time::Instant::now() + time::Duration::from_millis(500);
+        status.run();
+        assert_eq!(status.fields[0].value, None);
+    }
+
+    #[test]
+    fn resume() {
+        let resumes = rc::Rc::new(cell::Cell::new(0));
+        let mut status = Status::new(Box::new(output::Stdout::new(Markup::Plain, Colors::default())));
+        status.add(Box::new(Resumes(resumes.clone())), &config(300, None));
+        status.add(Box::new(Const("A")), &config(60, None));
+        status.run();
+        assert!(status.next_update().is_some());
+        status.resume(time::Instant::now());
+        assert_eq!(resumes.get(), 1);
+        assert!(status.next_update().is_none());
+    }
+
+    #[test]
+    fn commands() {
+        let now = time::Instant::now();
+        let mut status = Status::new(Box::new(output::Stdout::new(Markup::Plain, Colors::default())));
+        status.add(Box::new(Const("A")), &config(30, None));
+        status.add(Box::new(Const("B")), &config(20, None));
+        status.run();
+        assert_eq!(status.command(Command::Hide("test".to_string()), now), Ok("ok".to_string()));
+        assert!(status.fields.iter().all(|field| field.hidden));
+        assert!(status.next_update().is_none());
+        status.run();
+        status.fields[1].hidden = false;
+        let dump: serde_json::Value = serde_json::from_str(&status.command(Command::Dump, now).unwrap()).unwrap();
+        assert_eq!(dump["bar"], "B");
+        assert_eq!(dump["fields"][0]["text"], "A");
+        assert_eq!(dump["fields"][0]["hidden"], true);
+        assert_eq!(dump["fields"][1]["severity"], "normal");
+        assert!(status.command(Command::Show("missing".to_string()), now).is_err());
+        assert!(status.command(Command::Action("test".to_string(), "up".to_string()), now).is_err());
+        assert_eq!(status.command(Command::Describe("test".to_string()), now), Ok("nothing to describe".to_string()));
+    }
+
+    #[test]
+    fn writes() {
+        let lines = rc::Rc::new(cell::RefCell::new(vec![]));
+        let mut status = Status::new(Box::new(Recorder(lines.clone())));
+        status.add(Box::new(Const("A")), &config(30, None));
+        status.add(Box::new(Const("B")), &config(20, None));
+        let now = time::Instant::now();
+        status.run();
+        status.show(now);
+        status.show(now);
+        // updated, but to the same values
+        status.on_signal(libc::SIGUSR1, None, now);
+        status.run();
+        status.show(now);
+        assert_eq!(*lines.borrow(), vec!["A │ B"]);
+        // changes within `coalesce` make one write
+        status.coalesce = time::Duration::from_millis(10);
+        status.command(Command::Hide("test".to_string()), now).unwrap();
+        status.show(now);
+        status.fields[1].hidden = false;
+        status.show(now + time::Duration::from_millis(5));
+        assert_eq!(lines.borrow().len(), 1);
+        status.show(now + time::Duration::from_millis(10));
+        assert_eq!(*lines.borrow(), vec!["A │ B", "B"]);
+    }
+
+    #[test]
+    fn reload() {
+        let blocks = |data: &str| Config::parse(data).unwrap().blocks;
+        let mut status = Status::new(Box::new(output::Stdout::new(Markup::Plain, Colors::default())));
+        status.load(&blocks("[[block]]\nname = \"time\"\nformat = \"A\"\n[[block]]\nname = \"load\"")).unwrap();
+        status.run();
+        assert_eq!(status.fields[0].value, Some(Text::normal("A")));
+        status.fields[0].hidden = true;
+        status.load(&blocks("[[block]]\nname = \"load\"\n[[block]]\nname = \"time\"\nformat = \"B\"")).unwrap();
+        let names: Vec<&str> = status.fields.iter().map(|field| &field.config.name[..]).collect();
+        assert_eq!(names, vec!["load", "time"]);
+        assert!(status.fields[1].hidden);
+        status.run();
+        assert_eq!(status.fields[1].value, Some(Text::normal("B")));
+        assert!(status.load(&blocks("[[block]]\nname = \"nonsense\"")).is_err());
+        assert_eq!(status.fields.len(), 2);
+    }
+
+    #[test]
+    fn settled() {
+        // a threaded block's error only comes with its next update
+        let mut broken = Threaded::new(Box::new(Broken), time::Duration::from_secs(1));
+        broken.init();
+        assert_eq!(settle(&mut broken, time::Duration::from_secs(1)).unwrap_err().to_string(), "broken");
+        let mut fine = Threaded::new(Box::new(Const("ok")), time::Duration::from_secs(1));
+        fine.init();
+        settle(&mut fine, time::Duration::from_secs(1)).unwrap();
+        assert_eq!(fine.render(), Some(Text::normal("ok")));
     }
 }
@@ -0,0 +1,180 @@
+use libc::{epoll_create1, epoll_ctl, epoll_wait, read, sigaddset, sigemptyset, signalfd, sigprocmask, timerfd_create, timerfd_settime};
+use std::os::unix::io::RawFd;
+use std::{mem, ptr, time};
+
+const MAX_EVENTS: usize = 16;
+const CLIENT: u64 = 1 << 32;  // tags Token::Client's fd
+
+// What woke the event loop up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Token {
+    Timer,
+    Signal,
+    Input,
+    // the configuration file was written
+    Config,
+    // the control socket, and a connection to it
+    Control,
+    Client(RawFd),
+    // the wall clock was set, or jumped over a suspend
+    Clock,
+    Block(usize),
+}
+
+impl Token {
+    fn to_u64(self) -> u64 {
+        match self {
+            Token::Timer => 0,
+            Token::Signal => 1,
+            Token::Input => 2,
+            Token::Config => 3,
+            Token::Control => 4,
+            Token::Client(fd) => CLIENT | fd as u64,
+            Token::Clock => 5,
+            Token::Block(idx) => 6 + idx as u64,
+        }
+    }
+
+    fn from_u64(value: u64) -> Self {
+        match value {
+            0 => Token::Timer,
+            1 => Token::Signal,
+            2 => Token::Input,
+            3 => Token::Config,
+            4 => Token::Control,
+            5 => Token::Clock,
+            value if value & CLIENT != 0 => Token::Client((value & !CLIENT) as RawFd),
+            idx => Token::Block(idx as usize - 6),
+        }
+    }
+}
+
+// Make the timerfd `fd` expire once, `timeout` from now
+pub fn arm_timer(fd: RawFd, timeout: time::Duration) -> crate::error::Result<()> {
+    // a zero it_value would disarm the timer
+    let timeout = timeout.max(time::Duration::from_nanos(1));
+    let spec = libc::itimerspec {
+        it_interval: libc::timespec { tv_sec: 0, tv_nsec: 0 },
+        it_value: libc::timespec { tv_sec: timeout.as_secs() as libc::time_t, tv_nsec: timeout.subsec_nanos() as libc::c_long },
+    };
+    os_check!(timerfd_settime(fd, 0, &spec, ptr::null_mut()))?;
+    Ok(())
+}
+
+// Waits on any number of file descriptors, plus a timer for the next
+// scheduled update.
+pub struct EventLoop {
+    epfd: RawFd,
+    timer: RawFd,
+    // never expires, but is cancelled when the wall clock is set
+    clock: RawFd,
+    signals: RawFd,
+}
+
+impl EventLoop {
+    pub fn new() -> Self {
+        let epfd = perror_check!(epoll_create1(libc::EPOLL_CLOEXEC));
+        let timer = perror_check!(timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC));
+        let clock = perror_check!(timerfd_create(libc::CLOCK_REALTIME, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC));
+        let result = Self { epfd, timer, clock, signals: -1 };
+        result.watch(timer, Token::Timer);
+        result.arm_clock();
+        result.watch(clock, Token::Clock);
+        result
+    }
+
+    // (re)arm the clock timer, which also clears its cancellation
+    fn arm_clock(&self) {
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec { tv_sec: 0, tv_nsec: 0 },
+            it_value: libc::timespec { tv_sec: libc::time_t::MAX, tv_nsec: 0 },
+        };
+        let clock = self.clock;
+        perror_check!(timerfd_settime(clock, libc::TFD_TIMER_ABSTIME | libc::TFD_TIMER_CANCEL_ON_SET, &spec, ptr::null_mut()));
+    }
+
+    // Deliver `signals` through the event loop (as Token::Signal) instead of
+    // their default actions.  Must be called before any threads are started,
+    // so they inherit the signal mask.
+    pub fn watch_signals(&mut self, signals: &[libc::c_int]) {
+        let mut mask: libc::sigset_t = unsafe { mem::zeroed() };
+        perror_check!(sigemptyset(&mut mask));
+        for &signal in signals {
+            perror_check!(sigaddset(&mut mask, signal));
+        }
+        perror_check!(sigprocmask(libc::SIG_BLOCK, &mask, ptr::null_mut()));
+        let old = self.signals;
+        self.signals = perror_check!(signalfd(old, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC));
+        if old < 0 {
+            self.watch(self.signals, Token::Signal);
+        }
+    }
+
+    // the signals received since the last call
+    pub fn read_signals(&self) -> Vec<libc::signalfd_siginfo> {
+        let mut result = vec![];
+        loop {
+            let mut info: libc::signalfd_siginfo = unsafe { mem::zeroed() };
+            let size = mem::size_of::<libc::signalfd_siginfo>();
+            if unsafe { read(self.signals, &mut info as *mut _ as *mut libc::c_void, size) } != size as isize {
+                break;
+            }
+            result.push(info);
+        }
+        result
+    }
+
+    pub fn watch(&self, fd: RawFd, token: Token) {
+        let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: token.to_u64() };
+        let epfd = self.epfd;
+        perror_check!(epoll_ctl(epfd, libc::EPOLL_CTL_ADD, fd, &mut event));
+    }
+
+    pub fn unwatch(&self, fd: RawFd) {
+        let mut event = libc::epoll_event { events: 0, u64: 0 };
+        let epfd = self.epfd;
+        perror_check!(epoll_ctl(epfd, libc::EPOLL_CTL_DEL, fd, &mut event));
+    }
+
+    // Sleep until `timeout` has passed or any of the watched file
+    // descriptors is readable, and return what caused the wakeup.
+    pub fn wait(&mut self, timeout: time::Duration) -> Vec<Token> {
+        let timer = self.timer;
+        if let Err(err) = arm_timer(timer, timeout) {
+            panic!("{}", err);
+        }
+
+        let mut events: [libc::epoll_event; MAX_EVENTS] = unsafe { mem::zeroed() };
+        let epfd = self.epfd;
+        let count = unsafe { epoll_wait(epfd, events.as_mut_ptr(), MAX_EVENTS as i32, -1) };
+        if count < 0 {
+            // EINTR, the caller will work out what's due
+            return vec![];
+        }
+        let mut result = vec![];
+        for event in &events[..count as usize] {
+            let token = Token::from_u64(event.u64);
+            if token == Token::Timer {
+                let mut expirations = 0u64;
+                unsafe { read(timer, &mut expirations as *mut _ as *mut libc::c_void, mem::size_of::<u64>()) };
+            }
+            if token == Token::Clock {
+                // readable until re-armed
+                self.arm_clock();
+            }
+            result.push(token);
+        }
+        result
+    }
+}
+
+impl Drop for EventLoop {
+    fn drop(&mut self) {
+        if self.signals >= 0 {
+            unsafe { libc::close(self.signals) };
+        }
+        unsafe { libc::close(self.clock) };
+        unsafe { libc::close(self.timer) };
+        unsafe { libc::close(self.epfd) };
+    }
+}
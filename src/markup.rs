@@ -0,0 +1,156 @@
+use crate::config::Colors;
+use serde::{Deserialize, Serialize};
+
+// How urgent a block's value is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Normal,
+    Good,
+    Warn,
+    Bad,
+}
+
+// A rendered block: plain text, coloured by its severity
+#[derive(Clone, Debug, PartialEq)]
+pub struct Text {
+    pub severity: Severity,
+    pub text: String,
+}
+
+impl Text {
+    pub fn new(severity: Severity, text: impl Into<String>) -> Self {
+        Self { severity, text: text.into() }
+    }
+
+    pub fn normal(text: impl Into<String>) -> Self {
+        Self::new(Severity::Normal, text)
+    }
+}
+
+// How severities are written into a line of text
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Markup {
+    // dwm's statuscolors patch: \x01-\x05 select a colour scheme
+    StatusColors,
+    // dwm's status2d patch: ^c#rrggbb^ ... ^d^
+    Status2d,
+    // 24-bit colour escapes, for terminals
+    Ansi,
+    Plain,
+}
+
+impl Markup {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "statuscolors" => Some(Markup::StatusColors),
+            "status2d" => Some(Markup::Status2d),
+            "ansi" => Some(Markup::Ansi),
+            "plain" => Some(Markup::Plain),
+            _ => None,
+        }
+    }
+
+    // Whatever needs to come before the text of a block
+    pub fn begin(&self, severity: Severity, colors: &Colors) -> String {
+        match (self, colors.get(severity)) {
+            (Markup::StatusColors, _) => match severity {
+                Severity::Normal => "",
+                Severity::Good => "\x03",
+                Severity::Warn => "\x04",
+                Severity::Bad => "\x05",
+            }.to_string(),
+            (Markup::Status2d, Some(color)) => format!("^c{}^", color),
+            (Markup::Ansi, Some(color)) => {
+                let (r, g, b) = rgb(color).unwrap_or((255, 255, 255));
+                format!("\x1b[38;2;{};{};{}m", r, g, b)
+            },
+            _ => "".to_string(),
+        }
+    }
+
+    // ... and after it, to get back to the normal colour
+    pub fn end(&self, severity: Severity) -> &'static str {
+        match (self, severity) {
+            (_, Severity::Normal) => "",
+            (Markup::StatusColors, _) => "\x01",
+            (Markup::Status2d, _) => "^d^",
+            (Markup::Ansi, _) => "\x1b[0m",
+            (Markup::Plain, _) => "",
+        }
+    }
+
+    // Whether `byte` is one of this markup's control bytes, so can't also be
+    // a statuscmd marker
+    pub fn reserves(&self, byte: u8) -> bool {
+        match self {
+            Markup::StatusColors => (1..=5).contains(&byte),
+            Markup::Ansi => byte == 0x1b,
+            _ => false,
+        }
+    }
+
+    // Make `text` safe to embed
+    pub fn escape(&self, text: &str) -> String {
+        let text = strip_controls(text);
+        match self {
+            // status2d has no escape for ^, so drop it
+            Markup::Status2d => text.replace('^', ""),
+            _ => text,
+        }
+    }
+}
+
+impl Colors {
+    pub fn get(&self, severity: Severity) -> Option<&str> {
+        match severity {
+            Severity::Normal => None,
+            Severity::Good => Some(&self.good),
+            Severity::Warn => Some(&self.warn),
+            Severity::Bad => Some(&self.bad),
+        }
+    }
+}
+
+// Drop NUL and other control characters, which would end the line early
+// or pass for statuscolors and statuscmd bytes
+pub fn strip_controls(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+// "#rrggbb"
+pub fn rgb(color: &str) -> Option<(u8, u8, u8)> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let part = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some((part(0)?, part(2)?, part(4)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rgb, strip_controls, Markup, Severity};
+    use crate::config::Colors;
+
+    #[test]
+    fn markups() {
+        let colors = Colors::default();
+        let wrap = |markup: Markup, severity| markup.begin(severity, &colors) + "x" + markup.end(severity);
+        assert_eq!(wrap(Markup::StatusColors, Severity::Bad), "\x05x\x01");
+        assert_eq!(wrap(Markup::StatusColors, Severity::Normal), "x");
+        assert_eq!(wrap(Markup::Status2d, Severity::Warn), "^c#ffff00^x^d^");
+        assert_eq!(wrap(Markup::Ansi, Severity::Good), "\x1b[38;2;0;255;0mx\x1b[0m");
+        assert_eq!(wrap(Markup::Plain, Severity::Bad), "x");
+        assert_eq!(strip_controls("a\0b\x05c\x1b[0m\x7f"), "abc[0m");
+        assert_eq!(Markup::StatusColors.escape("\x02up\x01"), "up");
+        assert_eq!(Markup::Status2d.escape("^c#ff0000^\0x"), "c#ff0000x");
+        assert!(Markup::StatusColors.reserves(2));
+        assert!(!Markup::StatusColors.reserves(6));
+        assert!(!Markup::Status2d.reserves(2));
+        assert_eq!(rgb("#12ab34"), Some((0x12, 0xab, 0x34)));
+        assert_eq!(rgb("12ab34"), None);
+        assert_eq!(rgb("#12ab3"), None);
+    }
+}
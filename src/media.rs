@@ -1,9 +1,31 @@
-use crate::prelude::*;
+use crate::block::Block;
+use crate::config::BlockConfig;
+use crate::error::{Error, Result};
+use crate::format::{Format, Values};
+use crate::markup::{Severity, Text};
 use mpris::{PlaybackStatus, PlayerFinder};
 
 static ICON: &str = "▶";
+static FORMAT: &str = "{icon} [{artist} - ]{title}";
 
-pub fn status() -> Option<String> {
+struct Track {
+    playing: bool,
+    artist: Option<String>,
+    title: String,
+}
+
+pub struct Media {
+    format: Format,
+    track: Option<Track>,
+}
+
+impl Media {
+    pub fn new(config: &BlockConfig) -> Self {
+        Self { format: config.get_format("format", FORMAT), track: None }
+    }
+}
+
+fn find_track() -> Option<Track> {
     match PlayerFinder::new() {
         Ok(player_finder) => {
             match player_finder.find_active() {
@@ -17,23 +39,23 @@ pub fn status() -> Option<String> {
                                 } else {
                                     "?"
                                 }
-                            };
-                            let track = match metadata.artists() {
+                            }.to_string();
+                            let artist = match metadata.artists() {
                                 Some(v) => {
                                     // YT Music seems to append '- Topic' to every artist
                                     let artists = str::replace(&v.join(" & "), " - Topic", "");
                                     if artists.len() > 0 {
-                                        Some(artists + " - ")
+                                        Some(artists)
                                     } else {
                                         None
                                     }
                                 },
                                 None => None,
-                            }.unwrap_or("".to_string()) + title;
+                            };
                             match player.get_playback_status() {
                                 Ok(status) => match status {
-                                    PlaybackStatus::Playing => Some(GOOD.to_string() + ICON + " " + &track),
-                                    PlaybackStatus::Paused => Some(WARN.to_string() + ICON + " " + &track),
+                                    PlaybackStatus::Playing => Some(Track { playing: true, artist, title }),
+                                    PlaybackStatus::Paused => Some(Track { playing: false, artist, title }),
                                     PlaybackStatus::Stopped => None,
                                 },
                                 Err(_e) => None,
@@ -48,3 +70,62 @@ pub fn status() -> Option<String> {
         Err(_e) => None,  // no D-Bus
     }
 }
+
+impl Block for Media {
+    fn update(&mut self) -> Result<()> {
+        // no player isn't an error, just nothing to show
+        self.track = find_track();
+        Ok(())
+    }
+
+    // left: play/pause, middle: previous, right: next
+    fn click(&mut self, button: u8) {
+        let action = match button {
+            1 => "play_pause",
+            2 => "previous",
+            3 => "next",
+            _ => { return; },
+        };
+        if let Err(err) = self.action(action) {
+            eprintln!("dwmstatus: media: {}", err);
+        }
+    }
+
+    fn buttons(&self) -> &'static [u8] {
+        &[1, 2, 3]
+    }
+
+    fn action(&mut self, action: &str) -> Result<()> {
+        let finder = PlayerFinder::new().map_err(|err| Error::Lib(err.to_string()))?;
+        let player = finder.find_active().map_err(|err| Error::NotFound(err.to_string()))?;
+        let result = match action {
+            "play_pause" => player.play_pause(),
+            "play" => player.play(),
+            "pause" => player.pause(),
+            "previous" => player.previous(),
+            "next" => player.next(),
+            _ => return Err(Error::NotFound(format!("no action {}", action))),
+        };
+        result.map_err(|err| Error::Lib(err.to_string()))
+    }
+
+    fn describe(&self) -> Option<String> {
+        let finder = match PlayerFinder::new() {
+            Ok(finder) => finder,
+            Err(err) => { return Some(format!("no D-Bus ({})", err)); },
+        };
+        Some(finder.find_active().map_or("no player".to_string(), |player| player.identity().to_string()))
+    }
+
+    fn render(&self) -> Option<Text> {
+        self.track.as_ref().map(|track| {
+            let mut values = Values::new();
+            values.insert("icon", ICON.to_string());
+            values.insert("title", track.title.clone());
+            if let Some(artist) = &track.artist {
+                values.insert("artist", artist.clone());
+            }
+            Text::new(if track.playing { Severity::Good } else { Severity::Warn }, self.format.render(&values))
+        })
+    }
+}
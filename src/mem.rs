@@ -1,28 +1,110 @@
 use std::io::prelude::*;
 use std::{fs, io};
-use std::str::FromStr;
+use crate::block::Block;
+use crate::config::BlockConfig;
+use crate::error::{self, Error, Result};
+use crate::format::{Format, Values};
+use crate::markup::{Severity, Text};
 use crate::prelude::*;
+use crate::root::Root;
 
 static ICON: &str= "💻";
+static FORMAT: &str = "{icon} {available}B";
 static FREE_WARN: u64 = 1 * 1024 * 1024 * 1024;  // 1Gb
 static FREE_BAD: u64 = 100 * 1024 * 1024;  // 100Mb
 
-pub fn status() -> String {
-    let fd = io::BufReader::new(fs::File::open("/proc/meminfo").unwrap());
-
-    for line in fd.lines() {
-        let line = line.unwrap();
-        let columns: Vec<&str> = line.splitn(2, ":").collect();
-        assert_eq!(columns.len(), 2);
-        if columns[0] == "MemAvailable" {
-            let columns: Vec<&str> = columns[1].trim().splitn(2, " ").collect();
-            assert_eq!(columns.len(), 2);
-            assert_eq!(columns[1], "kB");
-            let mem = u64::from_str(columns[0]).unwrap() * 1024;
-            let mut result = if mem < FREE_BAD { BAD } else if mem < FREE_WARN { WARN } else { "" }.to_string();
-            result = result + ICON + " " + &prefixed(mem as f64) + "B";
-            return result;
+pub struct Mem {
+    root: Root,
+    format: Format,
+    free_warn: u64,
+    free_bad: u64,
+    total: Option<u64>,
+    available: Option<u64>,
+}
+
+impl Mem {
+    pub fn new(config: &BlockConfig) -> Self {
+        Self {
+            root: config.root.clone(),
+            format: config.get_format("format", FORMAT),
+            free_warn: config.get_bytes("free_warn", FREE_WARN),
+            free_bad: config.get_bytes("free_bad", FREE_BAD),
+            total: None,
+            available: None,
+        }
+    }
+}
+
+impl Block for Mem {
+    fn update(&mut self) -> Result<()> {
+        let path = &self.root.path("/proc/meminfo");
+        let fd = io::BufReader::new(fs::File::open(path).map_err(error::io(path))?);
+
+        self.total = None;
+        self.available = None;
+        for line in fd.lines() {
+            let line = line.map_err(error::io(path))?;
+            let columns: Vec<&str> = line.splitn(2, ":").collect();
+            if columns.len() != 2 {
+                return Err(Error::Parse(format!("{}: unexpected line {:?}", path, line)));
+            }
+            // MemTotal comes first
+            if columns[0] == "MemTotal" || columns[0] == "MemAvailable" {
+                let kb: Vec<&str> = columns[1].trim().splitn(2, " ").collect();
+                if kb.len() != 2 || kb[1] != "kB" {
+                    return Err(Error::Parse(format!("{}: unexpected line {:?}", path, line)));
+                }
+                let bytes = error::parse::<u64>(path, kb[0])? * 1024;
+                if columns[0] == "MemTotal" {
+                    self.total = Some(bytes);
+                } else {
+                    self.available = Some(bytes);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn describe(&self) -> Option<String> {
+        let total = self.total.map_or("?".to_string(), |total| prefixed(total as f64));
+        Some(format!("{} ({}B total)", self.root.path("/proc/meminfo"), total))
+    }
+
+    fn render(&self) -> Option<Text> {
+        match self.available {
+            Some(mem) => {
+                let severity = if mem < self.free_bad { Severity::Bad } else if mem < self.free_warn { Severity::Warn } else { Severity::Normal };
+                let mut values = Values::new();
+                values.insert("icon", ICON.to_string());
+                values.insert("available", prefixed(mem as f64));
+                Some(Text::new(severity, self.format.render(&values)))
+            },
+            None => Some(Text::new(Severity::Bad, ICON.to_string() + " ?")),
         }
     }
-    BAD.to_string() + ICON + " ?"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mem;
+    use crate::block::Block;
+    use crate::markup::{Severity, Text};
+    use crate::root;
+
+    fn mem(fixture: &str, options: &str) -> Mem {
+        let config = root::config("ram", options, Some(fixture));
+        let mut mem = Mem::new(&config);
+        mem.update().unwrap();
+        mem
+    }
+
+    #[test]
+    fn available() {
+        assert_eq!(mem("intel-discharging", "").render(), Some(Text::new(Severity::Normal, "💻 5.2GB")));
+        assert!(mem("intel-discharging", "").describe().unwrap().ends_with("meminfo (15.5GB total)"));
+        assert_eq!(mem("intel-discharging", "free_warn = \"8G\"").render().unwrap().severity, Severity::Warn);
+        assert_eq!(mem("amd-desktop", "").render(), Some(Text::new(Severity::Bad, "💻 82.0MB")));
+        assert!(Mem::new(&root::config("ram", "", Some("multi-battery"))).update().is_err());
+    }
 }
@@ -0,0 +1,334 @@
+use crate::config::Colors;
+use crate::markup::{strip_controls, Markup, Severity, Text};
+use serde::{Deserialize, Serialize};
+use std::io::prelude::*;
+use std::os::unix::io::RawFd;
+use std::{ffi, io, process};
+use x11::xlib;
+
+static SEP: &str = " │ ";
+
+// One visible field, as handed to an Output
+pub struct Segment<'a> {
+    pub name: &'a str,
+    pub instance: usize,
+    pub marker: Option<u8>,
+    // the mouse buttons it does something with
+    pub buttons: &'a [u8],
+    pub text: &'a Text,
+}
+
+// Where the bar is shown, and possibly where clicks come from
+pub trait Output {
+    fn show(&mut self, segments: &[Segment]);
+
+    // readable when clicks() has something to return, None once the input
+    // has been closed
+    fn input_fd(&self) -> Option<RawFd> {
+        None
+    }
+
+    // clicks received since the last call, as (instance, button)
+    fn clicks(&mut self) -> Vec<(usize, u8)> {
+        vec![]
+    }
+}
+
+// Join the segments into one line, with severities written as `markup`.
+// statuscmd markers go right before the text, after any colour change.
+pub fn markup_line(segments: &[Segment], markup: Markup, colors: &Colors, statuscmd: bool) -> String {
+    let mut result = "".to_string();
+    for (i, segment) in segments.iter().enumerate() {
+        if i > 0 {
+            result += SEP;
+        }
+        result += &markup.begin(segment.text.severity, colors);
+        match segment.marker {
+            Some(marker) if statuscmd && marker > 0 => result.push(marker as char),
+            _ => (),
+        }
+        result += &markup.escape(&segment.text.text);
+        result += markup.end(segment.text.severity);
+    }
+    result
+}
+
+// The root window name, as read by dwm
+pub struct XRoot {
+    display: *mut xlib::Display,
+    statuscmd: bool,
+    markup: Markup,
+    colors: Colors,
+    // what was last stored, as every store makes dwm redraw
+    last: Option<ffi::CString>,
+}
+
+impl XRoot {
+    pub fn new(display: *mut xlib::Display, statuscmd: bool, markup: Markup, colors: Colors) -> Self {
+        Self { display, statuscmd, markup, colors, last: None }
+    }
+}
+
+impl Output for XRoot {
+    fn show(&mut self, segments: &[Segment]) {
+        let result = match ffi::CString::new(markup_line(segments, self.markup, &self.colors, self.statuscmd)) {
+            Ok(result) => result,
+            Err(err) => {
+                eprintln!("dwmstatus: bad status line: {}", err);
+                return;
+            },
+        };
+        if self.last.as_ref() == Some(&result) {
+            return;
+        }
+
+        unsafe {
+            xlib::XStoreName(self.display, xlib::XDefaultRootWindow(self.display), result.as_ptr());
+            xlib::XSync(self.display, false as i32);
+        }
+        self.last = Some(result);
+    }
+}
+
+// Plain lines on stdout
+pub struct Stdout {
+    markup: Markup,
+    colors: Colors,
+}
+
+impl Stdout {
+    pub fn new(markup: Markup, colors: Colors) -> Self {
+        Self { markup, colors }
+    }
+}
+
+impl Output for Stdout {
+    fn show(&mut self, segments: &[Segment]) {
+        println!("{}", markup_line(segments, self.markup, &self.colors, false));
+    }
+}
+
+// Complete lines read from a non-blocking file descriptor
+pub struct LineReader {
+    fd: RawFd,
+    buf: Vec<u8>,
+    pub eof: bool,
+}
+
+impl LineReader {
+    pub fn new(fd: RawFd) -> Self {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+        Self { fd, buf: vec![], eof: false }
+    }
+
+    pub fn lines(&mut self) -> Vec<String> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            let count = unsafe { libc::read(self.fd, chunk.as_mut_ptr() as *mut libc::c_void, chunk.len()) };
+            if count == 0 {
+                self.eof = true;
+            }
+            if count <= 0 {
+                break;
+            }
+            self.buf.extend_from_slice(&chunk[..count as usize]);
+        }
+        let mut result = vec![];
+        while let Some(end) = self.buf.iter().position(|&c| c == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            result.push(String::from_utf8_lossy(&line[..end]).into_owned());
+        }
+        result
+    }
+}
+
+// A line for a bar reading our stdout (i3bar, lemonbar).  Once it's gone (eg.
+// restarted, which starts a new dwmstatus), there's nothing left to do.
+fn write_line(line: &str) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    if let Err(err) = writeln!(stdout, "{}", line).and_then(|()| stdout.flush()) {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("dwmstatus: stdout: {}", err);
+        }
+        process::exit(0);
+    }
+}
+
+// JSON for i3bar, which can't go wrong for these types
+fn to_json(value: &impl Serialize) -> String {
+    match serde_json::to_string(value) {
+        Ok(json) => json,
+        Err(err) => {
+            eprintln!("dwmstatus: {}", err);
+            "null".to_string()
+        },
+    }
+}
+
+// The i3bar/swaybar JSON protocol, see i3bar-protocol(7)
+pub struct I3bar {
+    input: LineReader,
+    colors: Colors,
+}
+
+#[derive(Serialize)]
+struct I3Header {
+    version: u32,
+    click_events: bool,
+}
+
+#[derive(Serialize)]
+struct I3Block<'a> {
+    full_text: &'a str,
+    name: &'a str,
+    instance: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<&'a str>,
+    urgent: bool,
+}
+
+#[derive(Deserialize)]
+struct I3Click {
+    instance: Option<String>,
+    button: u8,
+}
+
+impl I3bar {
+    pub fn new(colors: Colors) -> Self {
+        let header = I3Header { version: 1, click_events: true };
+        write_line(&to_json(&header));
+        // the body is an infinite array of status lines
+        write_line("[");
+        Self { input: LineReader::new(libc::STDIN_FILENO), colors }
+    }
+}
+
+impl Output for I3bar {
+    fn show(&mut self, segments: &[Segment]) {
+        let blocks: Vec<I3Block> = segments.iter().map(|segment| {
+            let severity = segment.text.severity;
+            let color = self.colors.get(severity);
+            I3Block { full_text: &segment.text.text, name: segment.name, instance: segment.instance.to_string(), color, urgent: severity == Severity::Bad }
+        }).collect();
+        write_line(&(to_json(&blocks) + ","));
+    }
+
+    fn input_fd(&self) -> Option<RawFd> {
+        if self.input.eof { None } else { Some(self.input.fd) }
+    }
+
+    fn clicks(&mut self) -> Vec<(usize, u8)> {
+        let mut result = vec![];
+        for line in self.input.lines() {
+            // click events are an infinite array too, one per line
+            let line = line.trim().trim_start_matches(['[', ',']);
+            if line.is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<I3Click>(line) {
+                Ok(click) => {
+                    if let Some(instance) = click.instance.and_then(|instance| instance.parse().ok()) {
+                        result.push((instance, click.button));
+                    }
+                },
+                Err(err) => eprintln!("dwmstatus: bad click event: {}", err),
+            }
+        }
+        result
+    }
+}
+
+// lemonbar's formatting tags.  Clickable areas print "click <instance>
+// <button>" on lemonbar's stdout, which can be fed back to dwmstatus:
+//   mkfifo clicks; dwmstatus --output lemonbar < clicks | lemonbar > clicks
+pub struct Lemonbar {
+    input: LineReader,
+    colors: Colors,
+}
+
+impl Lemonbar {
+    pub fn new(colors: Colors) -> Self {
+        Self { input: LineReader::new(libc::STDIN_FILENO), colors }
+    }
+
+    fn format(&self, segment: &Segment) -> String {
+        let mut result = String::new();
+        // lemonbar only has room for a few areas (its -a), so none that do
+        // nothing
+        for button in segment.buttons {
+            result += &format!("%{{A{}:click {} {}:}}", button, segment.instance, button);
+        }
+        if let Some(color) = self.colors.get(segment.text.severity) {
+            result += &format!("%{{F{}}}", color);
+        }
+        result += &strip_controls(&segment.text.text).replace('%', "%%");
+        if segment.text.severity != Severity::Normal {
+            result += "%{F-}";
+        }
+        for _ in segment.buttons {
+            result += "%{A}";
+        }
+        result
+    }
+}
+
+impl Output for Lemonbar {
+    fn show(&mut self, segments: &[Segment]) {
+        let line: Vec<String> = segments.iter().map(|segment| self.format(segment)).collect();
+        write_line(&line.join(SEP));
+    }
+
+    fn input_fd(&self) -> Option<RawFd> {
+        if self.input.eof { None } else { Some(self.input.fd) }
+    }
+
+    fn clicks(&mut self) -> Vec<(usize, u8)> {
+        let mut result = vec![];
+        for line in self.input.lines() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                ["click", instance, button] => match (instance.parse(), button.parse()) {
+                    (Ok(instance), Ok(button)) => result.push((instance, button)),
+                    _ => eprintln!("dwmstatus: bad click event: {}", line),
+                },
+                [] => (),
+                _ => eprintln!("dwmstatus: bad click event: {}", line),
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{markup_line, Lemonbar, LineReader, Segment};
+    use crate::config::Colors;
+    use crate::markup::{Markup, Severity, Text};
+
+    #[test]
+    fn lines() {
+        let colors = Colors::default();
+        let (a, b) = (Text::normal("a"), Text::new(Severity::Bad, "b"));
+        let segments = [
+            Segment { name: "a", instance: 0, marker: None, buttons: &[], text: &a },
+            Segment { name: "b", instance: 1, marker: Some(6), buttons: &[], text: &b },
+        ];
+        assert_eq!(markup_line(&segments, Markup::StatusColors, &colors, false), "a │ \x05b\x01");
+        assert_eq!(markup_line(&segments, Markup::StatusColors, &colors, true), "a │ \x05\x06b\x01");
+        assert_eq!(markup_line(&segments, Markup::Status2d, &colors, false), "a │ ^c#ff0000^b^d^");
+        assert_eq!(markup_line(&segments, Markup::Plain, &colors, true), "a │ \x06b");
+    }
+
+    #[test]
+    fn lemonbar() {
+        let lemonbar = Lemonbar { input: LineReader { fd: -1, buf: vec![], eof: true }, colors: Colors::default() };
+        let text = Text::new(Severity::Bad, "5%");
+        let segment = Segment { name: "battery", instance: 2, marker: None, buttons: &[], text: &text };
+        assert_eq!(lemonbar.format(&segment), "%{F#ff0000}5%%%{F-}");
+        let text = Text::normal("🔉 40%");
+        let segment = Segment { name: "volume", instance: 3, marker: None, buttons: &[1, 4, 5], text: &text };
+        assert_eq!(lemonbar.format(&segment), "%{A1:click 3 1:}%{A4:click 3 4:}%{A5:click 3 5:}🔉 40%%%{A}%{A}%{A}");
+    }
+}
@@ -0,0 +1,382 @@
+// A pa_mainloop_api implementation on top of epoll, so PulseAudio can be
+// driven from dwmstatus's own event loop instead of owning it.
+//
+// All of PulseAudio's file descriptors, timers (as timerfds) and deferred
+// events (as an eventfd) are collected in a private epoll instance, whose fd
+// becomes readable whenever `dispatch()` has work to do.
+use libc::{c_void, close, epoll_create1, epoll_ctl, epoll_wait, eventfd, read, timerfd_create, timerfd_settime, timeval, write};
+use libpulse_sys::mainloop::api::*;
+use std::cell::RefCell;
+use std::os::unix::io::RawFd;
+use std::{mem, ptr};
+
+// set in tv_usec by pa_rtclock_now() & friends for times on the monotonic clock
+const PA_TIMEVAL_RTCLOCK: libc::suseconds_t = 1 << 30;
+const MAX_EVENTS: usize = 16;
+
+// epoll data for the eventfd used to wake up for deferred events.  Other
+// epoll data are pointers to IoEvent, or to TimeEvent tagged with TAG_TIME.
+const DEFER_TOKEN: u64 = 1;
+const TAG_TIME: u64 = 2;
+
+struct IoEvent {
+    lp: *const PaLoop,
+    fd: RawFd,
+    cb: pa_io_event_cb_t,
+    destroy: pa_io_event_destroy_cb_t,
+    userdata: *mut c_void,
+    dead: bool,
+}
+
+struct TimeEvent {
+    lp: *const PaLoop,
+    fd: RawFd,
+    clock: libc::clockid_t,
+    tv: timeval,
+    enabled: bool,
+    cb: pa_time_event_cb_t,
+    destroy: pa_time_event_destroy_cb_t,
+    userdata: *mut c_void,
+    dead: bool,
+}
+
+struct DeferEvent {
+    lp: *const PaLoop,
+    enabled: bool,
+    cb: pa_defer_event_cb_t,
+    destroy: pa_defer_event_destroy_cb_t,
+    userdata: *mut c_void,
+    dead: bool,
+}
+
+#[derive(Default)]
+struct Events {
+    ios: Vec<*mut IoEvent>,
+    times: Vec<*mut TimeEvent>,
+    defers: Vec<*mut DeferEvent>,
+}
+
+// PulseAudio calls back into the api from within dispatch(), so everything
+// that changes is behind a RefCell, and never borrowed across a callback.
+pub struct PaLoop {
+    api: pa_mainloop_api,
+    epfd: RawFd,
+    deferfd: RawFd,
+    events: RefCell<Events>,
+}
+
+fn epoll_flags(events: pa_io_event_flags_t) -> u32 {
+    let mut result = 0;
+    if events & PA_IO_EVENT_INPUT != 0 {
+        result |= libc::EPOLLIN;
+    }
+    if events & PA_IO_EVENT_OUTPUT != 0 {
+        result |= libc::EPOLLOUT;
+    }
+    result as u32
+}
+
+fn pa_flags(events: u32) -> pa_io_event_flags_t {
+    let events = events as i32;
+    let mut result = PA_IO_EVENT_NULL;
+    if events & libc::EPOLLIN != 0 {
+        result |= PA_IO_EVENT_INPUT;
+    }
+    if events & libc::EPOLLOUT != 0 {
+        result |= PA_IO_EVENT_OUTPUT;
+    }
+    if events & libc::EPOLLHUP != 0 {
+        result |= PA_IO_EVENT_HANGUP;
+    }
+    if events & libc::EPOLLERR != 0 {
+        result |= PA_IO_EVENT_ERROR;
+    }
+    result
+}
+
+fn lp_from_api<'a>(a: *const pa_mainloop_api) -> &'a PaLoop {
+    unsafe { &*((*a).userdata as *const PaLoop) }
+}
+
+extern "C" fn io_new(a: *const pa_mainloop_api, fd: i32, events: pa_io_event_flags_t, cb: pa_io_event_cb_t, userdata: *mut c_void) -> *mut pa_io_event {
+    let lp = lp_from_api(a);
+    let e = Box::into_raw(Box::new(IoEvent { lp, fd, cb, destroy: None, userdata, dead: false }));
+    let mut event = libc::epoll_event { events: epoll_flags(events), u64: e as u64 };
+    let epfd = lp.epfd;
+    perror_check!(epoll_ctl(epfd, libc::EPOLL_CTL_ADD, fd, &mut event));
+    lp.events.borrow_mut().ios.push(e);
+    e as *mut pa_io_event
+}
+
+extern "C" fn io_enable(e: *mut pa_io_event, events: pa_io_event_flags_t) {
+    let e = e as *mut IoEvent;
+    let (epfd, fd) = unsafe { ((*(*e).lp).epfd, (*e).fd) };
+    let mut event = libc::epoll_event { events: epoll_flags(events), u64: e as u64 };
+    perror_check!(epoll_ctl(epfd, libc::EPOLL_CTL_MOD, fd, &mut event));
+}
+
+extern "C" fn io_free(e: *mut pa_io_event) {
+    let io = unsafe { &mut *(e as *mut IoEvent) };
+    let lp = unsafe { &*io.lp };
+    let (epfd, fd) = (lp.epfd, io.fd);
+    unsafe { epoll_ctl(epfd, libc::EPOLL_CTL_DEL, fd, ptr::null_mut()) };
+    io.dead = true;
+    if let Some(destroy) = io.destroy {
+        destroy(&lp.api, e, io.userdata);
+    }
+}
+
+extern "C" fn io_set_destroy(e: *mut pa_io_event, cb: pa_io_event_destroy_cb_t) {
+    unsafe { (*(e as *mut IoEvent)).destroy = cb };
+}
+
+// arm the timerfd for `tv`, or disarm it if `tv` is NULL
+fn time_arm(e: &mut TimeEvent, tv: *const timeval) {
+    let mut spec: libc::itimerspec = unsafe { mem::zeroed() };
+    e.enabled = !tv.is_null();
+    if !e.enabled {
+        if e.fd >= 0 {
+            let fd = e.fd;
+            perror_check!(timerfd_settime(fd, 0, &spec, ptr::null_mut()));
+        }
+        return;
+    }
+
+    e.tv = unsafe { *tv };
+    let mut tv = e.tv;
+    let clock = if tv.tv_usec & PA_TIMEVAL_RTCLOCK != 0 {
+        tv.tv_usec &= !PA_TIMEVAL_RTCLOCK;
+        libc::CLOCK_MONOTONIC
+    } else {
+        libc::CLOCK_REALTIME
+    };
+    // a timerfd's clock is fixed when it is created
+    if e.fd < 0 || e.clock != clock {
+        let epfd = unsafe { (*e.lp).epfd };
+        if e.fd >= 0 {
+            unsafe { epoll_ctl(epfd, libc::EPOLL_CTL_DEL, e.fd, ptr::null_mut()) };
+            unsafe { close(e.fd) };
+        }
+        let fd = perror_check!(timerfd_create(clock, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC));
+        let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: e as *mut TimeEvent as u64 | TAG_TIME };
+        perror_check!(epoll_ctl(epfd, libc::EPOLL_CTL_ADD, fd, &mut event));
+        e.fd = fd;
+        e.clock = clock;
+    }
+    spec.it_value = libc::timespec { tv_sec: tv.tv_sec, tv_nsec: tv.tv_usec as libc::c_long * 1000 };
+    if spec.it_value.tv_sec == 0 && spec.it_value.tv_nsec == 0 {
+        // zero would disarm the timer instead
+        spec.it_value.tv_nsec = 1;
+    }
+    let fd = e.fd;
+    perror_check!(timerfd_settime(fd, libc::TFD_TIMER_ABSTIME, &spec, ptr::null_mut()));
+}
+
+extern "C" fn time_new(a: *const pa_mainloop_api, tv: *const timeval, cb: pa_time_event_cb_t, userdata: *mut c_void) -> *mut pa_time_event {
+    let lp = lp_from_api(a);
+    let e = Box::into_raw(Box::new(TimeEvent { lp, fd: -1, clock: libc::CLOCK_MONOTONIC, tv: timeval { tv_sec: 0, tv_usec: 0 }, enabled: false, cb, destroy: None, userdata, dead: false }));
+    time_arm(unsafe { &mut *e }, tv);
+    lp.events.borrow_mut().times.push(e);
+    e as *mut pa_time_event
+}
+
+extern "C" fn time_restart(e: *mut pa_time_event, tv: *const timeval) {
+    time_arm(unsafe { &mut *(e as *mut TimeEvent) }, tv);
+}
+
+extern "C" fn time_free(e: *mut pa_time_event) {
+    let time = unsafe { &mut *(e as *mut TimeEvent) };
+    let lp = unsafe { &*time.lp };
+    if time.fd >= 0 {
+        unsafe { epoll_ctl(lp.epfd, libc::EPOLL_CTL_DEL, time.fd, ptr::null_mut()) };
+        unsafe { close(time.fd) };
+        time.fd = -1;
+    }
+    time.dead = true;
+    if let Some(destroy) = time.destroy {
+        destroy(&lp.api, e, time.userdata);
+    }
+}
+
+extern "C" fn time_set_destroy(e: *mut pa_time_event, cb: pa_time_event_destroy_cb_t) {
+    unsafe { (*(e as *mut TimeEvent)).destroy = cb };
+}
+
+extern "C" fn defer_new(a: *const pa_mainloop_api, cb: pa_defer_event_cb_t, userdata: *mut c_void) -> *mut pa_defer_event {
+    let lp = lp_from_api(a);
+    let e = Box::into_raw(Box::new(DeferEvent { lp, enabled: true, cb, destroy: None, userdata, dead: false }));
+    lp.events.borrow_mut().defers.push(e);
+    lp.wake();
+    e as *mut pa_defer_event
+}
+
+extern "C" fn defer_enable(e: *mut pa_defer_event, b: i32) {
+    let defer = unsafe { &mut *(e as *mut DeferEvent) };
+    defer.enabled = b != 0;
+    if defer.enabled {
+        unsafe { &*defer.lp }.wake();
+    }
+}
+
+extern "C" fn defer_free(e: *mut pa_defer_event) {
+    let defer = unsafe { &mut *(e as *mut DeferEvent) };
+    defer.dead = true;
+    if let Some(destroy) = defer.destroy {
+        destroy(unsafe { &(*defer.lp).api }, e, defer.userdata);
+    }
+}
+
+extern "C" fn defer_set_destroy(e: *mut pa_defer_event, cb: pa_defer_event_destroy_cb_t) {
+    unsafe { (*(e as *mut DeferEvent)).destroy = cb };
+}
+
+extern "C" fn quit(_a: *const pa_mainloop_api, _retval: i32) {
+    // nothing to do, the loop belongs to dwmstatus
+}
+
+impl PaLoop {
+    // Boxed, since the api's userdata points back to the PaLoop
+    pub fn new() -> Box<Self> {
+        let epfd = perror_check!(epoll_create1(libc::EPOLL_CLOEXEC));
+        let deferfd = perror_check!(eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC));
+        let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: DEFER_TOKEN };
+        perror_check!(epoll_ctl(epfd, libc::EPOLL_CTL_ADD, deferfd, &mut event));
+        let mut result = Box::new(Self {
+            api: pa_mainloop_api {
+                userdata: ptr::null_mut(),
+                io_new: Some(io_new),
+                io_enable: Some(io_enable),
+                io_free: Some(io_free),
+                io_set_destroy: Some(io_set_destroy),
+                time_new: Some(time_new),
+                time_restart: Some(time_restart),
+                time_free: Some(time_free),
+                time_set_destroy: Some(time_set_destroy),
+                defer_new: Some(defer_new),
+                defer_enable: Some(defer_enable),
+                defer_free: Some(defer_free),
+                defer_set_destroy: Some(defer_set_destroy),
+                quit: Some(quit),
+            },
+            epfd,
+            deferfd,
+            events: RefCell::new(Events::default()),
+        });
+        result.api.userdata = &mut *result as *mut PaLoop as *mut c_void;
+        result
+    }
+
+    pub fn api(&self) -> *const pa_mainloop_api {
+        &self.api
+    }
+
+    // readable whenever dispatch() has something to do
+    pub fn fd(&self) -> RawFd {
+        self.epfd
+    }
+
+    fn wake(&self) {
+        let one = 1u64;
+        unsafe { write(self.deferfd, &one as *const _ as *const c_void, mem::size_of::<u64>()) };
+    }
+
+    // Run the callbacks of any ready events, without blocking.
+    pub fn dispatch(&self) {
+        let mut events: [libc::epoll_event; MAX_EVENTS] = unsafe { mem::zeroed() };
+        let epfd = self.epfd;
+        let count = unsafe { epoll_wait(epfd, events.as_mut_ptr(), MAX_EVENTS as i32, 0) };
+        let api = &self.api as *const pa_mainloop_api;
+        for event in &events[..count.max(0) as usize] {
+            let data = event.u64;
+            if data == DEFER_TOKEN {
+                let mut value = 0u64;
+                unsafe { read(self.deferfd, &mut value as *mut _ as *mut c_void, mem::size_of::<u64>()) };
+            } else if data & TAG_TIME != 0 {
+                let time = unsafe { &mut *((data & !TAG_TIME) as *mut TimeEvent) };
+                let mut expirations = 0u64;
+                unsafe { read(time.fd, &mut expirations as *mut _ as *mut c_void, mem::size_of::<u64>()) };
+                if !time.dead && time.enabled {
+                    time.enabled = false;
+                    if let Some(cb) = time.cb {
+                        cb(api, time as *mut TimeEvent as *mut pa_time_event, &time.tv, time.userdata);
+                    }
+                }
+            } else {
+                let io = unsafe { &mut *(data as *mut IoEvent) };
+                if !io.dead {
+                    if let Some(cb) = io.cb {
+                        cb(api, io as *mut IoEvent as *mut pa_io_event, io.fd, pa_flags(event.events), io.userdata);
+                    }
+                }
+            }
+        }
+
+        // callbacks may add or free defer events as we go, so work on a copy
+        let defers = self.events.borrow().defers.clone();
+        for e in defers {
+            let defer = unsafe { &mut *e };
+            if !defer.dead && defer.enabled {
+                if let Some(cb) = defer.cb {
+                    cb(api, e as *mut pa_defer_event, defer.userdata);
+                }
+            }
+        }
+        if self.events.borrow().defers.iter().any(|&e| unsafe { !(*e).dead && (*e).enabled }) {
+            self.wake();
+        }
+        self.collect();
+    }
+
+    // deallocate events which PulseAudio has freed
+    fn collect(&self) {
+        let mut events = self.events.borrow_mut();
+        events.ios.retain(|&e| {
+            let dead = unsafe { (*e).dead };
+            if dead {
+                drop(unsafe { Box::from_raw(e) });
+            }
+            !dead
+        });
+        events.times.retain(|&e| {
+            let dead = unsafe { (*e).dead };
+            if dead {
+                drop(unsafe { Box::from_raw(e) });
+            }
+            !dead
+        });
+        events.defers.retain(|&e| {
+            let dead = unsafe { (*e).dead };
+            if dead {
+                drop(unsafe { Box::from_raw(e) });
+            }
+            !dead
+        });
+    }
+}
+
+impl Drop for PaLoop {
+    fn drop(&mut self) {
+        let (ios, times, defers) = {
+            let events = self.events.borrow();
+            (events.ios.clone(), events.times.clone(), events.defers.clone())
+        };
+        for e in ios {
+            if unsafe { !(*e).dead } {
+                io_free(e as *mut pa_io_event);
+            }
+        }
+        for e in times {
+            if unsafe { !(*e).dead } {
+                time_free(e as *mut pa_time_event);
+            }
+        }
+        for e in defers {
+            if unsafe { !(*e).dead } {
+                defer_free(e as *mut pa_defer_event);
+            }
+        }
+        self.collect();
+        unsafe { close(self.deferfd) };
+        unsafe { close(self.epfd) };
+    }
+}
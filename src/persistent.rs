@@ -0,0 +1,258 @@
+use crate::block::{Block, BUTTONS};
+use crate::config::BlockConfig;
+use crate::error::{Error, Result};
+use crate::format::Format;
+use crate::markup::Text;
+use crate::output::LineReader;
+use crate::prelude::*;
+use crate::script::{parse_line, render_output};
+use libc::{epoll_create1, epoll_ctl};
+use std::io::prelude::*;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::{process, thread, time};
+
+static FORMAT: &str = "{output}";
+// how long the command gets to exit on SIGTERM, with the bar waiting
+static REAP_GRACE: time::Duration = time::Duration::from_millis(200);
+
+struct Child {
+    process: process::Child,
+    stdin: Option<process::ChildStdin>,
+    // keeps the pipe open for `lines`
+    _stdout: process::ChildStdout,
+    lines: LineReader,
+}
+
+// A command that keeps running, printing a line whenever its value changes
+// (`persistent = true`).  Clicks are written to its stdin, one button number
+// per line.  If it exits, that's an error, and it's started again on the next
+// update.
+pub struct Persistent {
+    command: String,
+    format: Format,
+    severity_prefix: bool,
+    child: Option<Child>,
+    // holds the child's stdout, so the main loop can watch the same fd across
+    // restarts
+    epfd: RawFd,
+    output: Option<Text>,
+}
+
+impl Persistent {
+    pub fn new(config: &BlockConfig) -> Self {
+        Self {
+            command: config.get_str("command", "").to_string(),
+            format: config.get_format("format", FORMAT),
+            severity_prefix: config.get_bool("severity_prefix", false),
+            child: None,
+            epfd: -1,
+            output: None,
+        }
+    }
+
+    fn spawn(&mut self) -> Result<()> {
+        let mut command = shell(&self.command);
+        command.stdin(process::Stdio::piped()).stdout(process::Stdio::piped()).stderr(process::Stdio::inherit());
+        // its own process group, so whatever sh starts can be killed with it
+        unsafe {
+            command.pre_exec(|| {
+                libc::setpgid(0, 0);
+                Ok(())
+            });
+        }
+        let mut process = command.spawn().map_err(|err| Error::Io(self.command.clone(), err))?;
+        let stdin = process.stdin.take();
+        let stdout = process.stdout.take().unwrap();
+        let fd = stdout.as_raw_fd();
+        if self.epfd >= 0 {
+            let mut event = libc::epoll_event { events: libc::EPOLLIN as u32, u64: 0 };
+            let epfd = self.epfd;
+            if let Err(err) = os_check!(epoll_ctl(epfd, libc::EPOLL_CTL_ADD, fd, &mut event)) {
+                kill_group(&process, libc::SIGKILL);
+                let _ = process.wait();
+                return Err(err);
+            }
+        }
+        if let Some(stdin) = &stdin {
+            // a child that doesn't read its clicks mustn't block us
+            let flags = unsafe { libc::fcntl(stdin.as_raw_fd(), libc::F_GETFL) };
+            unsafe { libc::fcntl(stdin.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) };
+        }
+        self.child = Some(Child { process, stdin, lines: LineReader::new(fd), _stdout: stdout });
+        Ok(())
+    }
+
+    // Stop watching and reap the child, returning how it ended
+    fn reap(&mut self) -> Option<process::ExitStatus> {
+        let mut child = self.child.take()?;
+        // closing stdout, with the rest of `child`, removes it from epfd
+        drop(child.stdin.take());
+        kill_group(&child.process, libc::SIGTERM);
+        let start = time::Instant::now();
+        while !exited(&child.process) && start.elapsed() < REAP_GRACE {
+            thread::sleep(time::Duration::from_millis(5));
+        }
+        // whatever is left, eg. a command that traps SIGTERM
+        kill_group(&child.process, libc::SIGKILL);
+        child.process.wait().ok()
+    }
+}
+
+// The child is its group's leader, and keeps the group until it's waited for
+fn kill_group(process: &process::Child, signal: libc::c_int) {
+    unsafe { libc::kill(-(process.id() as libc::pid_t), signal) };
+}
+
+// Without reaping it, so the group stays ours
+fn exited(process: &process::Child) -> bool {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+    let ret = unsafe { libc::waitid(libc::P_PID, process.id(), &mut info, flags) };
+    ret != 0 || unsafe { info.si_pid() } != 0
+}
+
+impl Drop for Persistent {
+    fn drop(&mut self) {
+        self.reap();
+        if self.epfd >= 0 {
+            unsafe { libc::close(self.epfd) };
+        }
+    }
+}
+
+impl Block for Persistent {
+    fn init(&mut self) {
+        self.epfd = match os_check!(epoll_create1(libc::EPOLL_CLOEXEC)) {
+            Ok(epfd) => epfd,
+            Err(err) => {
+                // updates still come every period
+                eprintln!("dwmstatus: {}: {}", self.command, err);
+                -1
+            },
+        };
+    }
+
+    fn update(&mut self) -> Result<()> {
+        if self.child.is_none() {
+            self.spawn()?;
+        }
+        let child = self.child.as_mut().unwrap();
+        if let Some(line) = child.lines.lines().pop() {
+            self.output = parse_line(&line, self.severity_prefix);
+        }
+        if child.lines.eof {
+            self.output = None;
+            let status = self.reap().map_or("killed".to_string(), |status| status.to_string());
+            return Err(Error::Lib(format!("{}: {}", self.command, status)));
+        }
+        Ok(())
+    }
+
+    fn click(&mut self, button: u8) {
+        if let Some(stdin) = self.child.as_mut().and_then(|child| child.stdin.as_mut()) {
+            // lost if the child isn't reading
+            let _ = stdin.write_all(format!("{}\n", button).as_bytes());
+        }
+    }
+
+    fn buttons(&self) -> &'static [u8] {
+        BUTTONS
+    }
+
+    fn render(&self) -> Option<Text> {
+        render_output(&self.format, self.output.as_ref())
+    }
+
+    fn event_fd(&self) -> Option<RawFd> {
+        if self.epfd >= 0 { Some(self.epfd) } else { None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Persistent;
+    use crate::block::Block;
+    use crate::markup::{Severity, Text};
+    use crate::root;
+
+    fn persistent(command: &str) -> Persistent {
+        let config = root::config("test", &format!("command = {:?}\npersistent = true\nseverity_prefix = true", command), None);
+        let mut block = Persistent::new(&config);
+        block.init();
+        block
+    }
+
+    // until the child has something to say
+    fn wait(block: &Persistent) {
+        let mut fd = libc::pollfd { fd: block.event_fd().unwrap(), events: libc::POLLIN, revents: 0 };
+        assert_eq!(unsafe { libc::poll(&mut fd, 1, 5000) }, 1);
+    }
+
+    // the child's lines may come before or after we look
+    fn expect(block: &mut Persistent, text: Text) {
+        block.update().unwrap();
+        while block.render().as_ref() != Some(&text) {
+            wait(block);
+            block.update().unwrap();
+        }
+    }
+
+    #[test]
+    fn stream() {
+        // echoes each click back, as a warning
+        let mut block = persistent("echo ready; while read button; do echo \"WARN: $button\"; done");
+        expect(&mut block, Text::normal("ready"));
+        block.click(3);
+        expect(&mut block, Text::new(Severity::Warn, "3"));
+    }
+
+    #[test]
+    fn controls() {
+        let mut block = persistent("printf 'a\\000b\\002c\\n'; exec cat");
+        expect(&mut block, Text::normal("abc"));
+    }
+
+    #[test]
+    fn group() {
+        let mut block = persistent("sleep 60 & echo $!; exec cat");
+        block.update().unwrap();
+        while block.render().is_none() {
+            wait(&block);
+            block.update().unwrap();
+        }
+        let pid = block.render().unwrap().text;
+        drop(block);
+        // gone, or a zombie waiting for init
+        let stat = format!("/proc/{}/stat", pid);
+        for _ in 0..100 {
+            match std::fs::read_to_string(&stat) {
+                Ok(stat) if !stat.contains(") Z ") => std::thread::sleep(std::time::Duration::from_millis(50)),
+                _ => return,
+            }
+        }
+        panic!("sleep {} outlived its block", pid);
+    }
+
+    #[test]
+    fn stubborn() {
+        let mut block = persistent("trap '' TERM; echo ready; while :; do sleep 1; done");
+        expect(&mut block, Text::normal("ready"));
+        let start = std::time::Instant::now();
+        drop(block);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn restart() {
+        let mut block = persistent("echo once");
+        // the line and the exit may come apart
+        while block.update().is_ok() {
+            wait(&block);
+        }
+        assert!(block.child.is_none());
+        assert_eq!(block.render(), None);
+        block.update().unwrap();
+        assert!(block.child.is_some());
+    }
+}
@@ -1,22 +1,48 @@
 #![macro_use]
 
+// call a libc function, panicking with strerror() if it fails, or evaluate to its result
 macro_rules! perror_check {
-    ($f: ident ( $($x: expr),* ) ) => {
+    ($f: ident ( $($x: expr),* ) ) => {{
         let err = unsafe { $f( $($x),* ) };
         if err < 0 {
             use std::ffi;
             use libc;
             let errno = unsafe { *libc::__errno_location() };
             let err_str = unsafe { ffi::CStr::from_ptr(libc::strerror(errno)) }.to_str().expect("Failed to convert strerror result to string");
-            panic!(format!("{}: {}", stringify!($f), err_str));
+            panic!("{}: {}", stringify!($f), err_str);
         }
-    }
+        err
+    }}
+}
+
+// call a libc function, evaluating to its result, or to Err(Error::Os) with
+// errno if it fails
+macro_rules! os_check {
+    ($f: ident ( $($x: expr),* ) ) => {{
+        let result = unsafe { $f( $($x),* ) };
+        if result < 0 {
+            Err(crate::error::Error::Os(stringify!($f), std::io::Error::last_os_error()))
+        } else {
+            Ok(result)
+        }
+    }}
 }
 
-pub static NORM: &'static str = "\x01";
-pub static GOOD: &'static str = "\x03";
-pub static WARN: &'static str = "\x04";
-pub static BAD: &'static str = "\x05";
+// A command run through the shell, with the signals dwmstatus watches unblocked again
+pub fn shell(cmd: &str) -> std::process::Command {
+    use std::os::unix::process::CommandExt;
+    let mut result = std::process::Command::new("sh");
+    result.arg("-c").arg(cmd);
+    unsafe {
+        result.pre_exec(|| {
+            let mut mask: libc::sigset_t = std::mem::zeroed();
+            libc::sigemptyset(&mut mask);
+            libc::sigprocmask(libc::SIG_SETMASK, &mask, std::ptr::null_mut());
+            Ok(())
+        });
+    }
+    result
+}
 
 static PREFIXES: &str = "kMGT";
 
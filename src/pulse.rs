@@ -1,25 +1,35 @@
 use libc::c_void;
-use std::{ffi, ptr, time};
-use crate::prelude::*;
+use std::os::unix::io::RawFd;
+use std::{ffi, ptr};
+use crate::block::Block;
+use crate::config::BlockConfig;
+use crate::error::{Error, Result};
+use crate::format::{Format, Values};
+use crate::markup::{Severity, Text};
+use crate::paloop::PaLoop;
 
 use libpulse_sys::context::*;
 use libpulse_sys::error::pa_strerror;
-use libpulse_sys::mainloop::api::pa_mainloop_api;
-use libpulse_sys::mainloop::standard::*;
+use libpulse_sys::operation::pa_operation_unref;
 use libpulse_sys::volume::*;
 
 static VOL_MUTE: &'static str = "🔇";
 static VOL_UNMUTE: &'static str = "🔈";
 static VOL_LOW: &'static str = "🔉";
 static VOL_HIGH: &'static str = "🔊";
+static FORMAT: &str = "{icon} {volume}%";
+static VOL_STEP: pa_volume_t = PA_VOLUME_NORM / 20;  // 5%
 
 pub struct Pulse {
-    mainloop: *mut pa_mainloop,
-    mainloop_api: *const pa_mainloop_api,
+    format: Format,
+    mainloop: Box<PaLoop>,
     context: *mut pa_context,
     default_sink_name: Option<String>,
+    volume: Option<pa_cvolume>,
     mute: bool,
     level: Option<u8>,
+    // why the connection failed, reported by the next update
+    error: Option<String>,
 }
 
 extern "C" fn _state_cb(_context: *mut pa_context, data: *mut c_void) {
@@ -36,10 +46,12 @@ extern "C" fn _server_info_cb(_context: *mut pa_context, i: *const pa_server_inf
 }
 
 extern "C" fn _sink_info_cb(_context: *mut pa_context, i: *const pa_sink_info, eol: i32, data: *mut c_void) {
-    if eol <= 0 || ! i.is_null() {
-        let data: &mut Pulse = unsafe { &mut *(data as *mut Pulse) };
+    let data: &mut Pulse = unsafe { &mut *(data as *mut Pulse) };
+    if eol == 0 && ! i.is_null() {
         let i = unsafe { i.as_ref() }.unwrap();
         data.on_sink_info_event(i)
+    } else if eol < 0 {
+        data.on_sink_info_error()
     }
 }
 
@@ -50,97 +62,43 @@ extern "C" fn _subscribe_cb(_context: *mut pa_context, t: pa_subscription_event_
 
 impl Drop for Pulse {
     fn drop(&mut self) {
-        if !self.context.is_null() {
-            unsafe { pa_context_unref(self.context) };
+        let context = self.context;
+        if !context.is_null() {
+            // disconnecting calls back with PA_CONTEXT_TERMINATED, which would
+            // unref the context before we do
+            unsafe { pa_context_set_state_callback(context, None, ptr::null_mut()) };
+            unsafe { pa_context_set_subscribe_callback(context, None, ptr::null_mut()) };
+            unsafe { pa_context_disconnect(context) };
+            unsafe { pa_context_unref(context) };
+            self.context = ptr::null_mut();
         }
-        unsafe { pa_mainloop_quit(self.mainloop, 0) };
-        unsafe { pa_mainloop_run(self.mainloop, ptr::null_mut()) };
-        unsafe { pa_mainloop_free(self.mainloop) };
     }
 }
 
 impl Pulse {
-    pub fn new() -> Self {
-        let mainloop = unsafe { pa_mainloop_new() };
-        if mainloop.is_null() {
-            panic!("pa_mainloop_new failed");
-        }
-
-        let mainloop_api = unsafe { pa_mainloop_get_api(mainloop) };
-        if mainloop_api.is_null() {
-            panic!("pa_mainloop_get_api failed");
-        }
-
-        Self { mainloop, mainloop_api, context: ptr::null_mut(), default_sink_name: None, mute: false, level: None }
+    pub fn new(config: &BlockConfig) -> Self {
+        Self { format: config.get_format("format", FORMAT), mainloop: PaLoop::new(), context: ptr::null_mut(), default_sink_name: None, volume: None, mute: false, level: None, error: None }
     }
 
-    pub fn sleep(&mut self, period: time::Duration) {
-        let period = period.as_micros() as i32;
-        let ret = unsafe { pa_mainloop_prepare(self.mainloop, period) };
-        if ret < 0 {
-            let err_str = unsafe { ffi::CStr::from_ptr(pa_strerror(ret)) }.to_str().unwrap();
-            panic!("pa_mainloop_prepare failed: {}", err_str);
-        }
-        let ret = unsafe { pa_mainloop_poll(self.mainloop) };
-        if ret < 0 {
-            let err_str = unsafe { ffi::CStr::from_ptr(pa_strerror(ret)) }.to_str().unwrap();
-            panic!("pa_mainloop_poll failed: {}", err_str);
-        }
-        let ret = unsafe { pa_mainloop_dispatch(self.mainloop) };
-        if ret < 0 {
-            let err_str = unsafe { ffi::CStr::from_ptr(pa_strerror(ret)) }.to_str().unwrap();
-            panic!("pa_mainloop_dispatch failed: {}", err_str);
+    // Change the default sink's mute/volume.  The result comes back as a
+    // subscription event like any other change.
+    fn set_mute(&self, mute: bool) {
+        if let Some(name) = &self.default_sink_name {
+            let name_c = ffi::CString::new(&name[..]).unwrap();
+            let op = unsafe { pa_context_set_sink_mute_by_name(self.context, name_c.as_ptr(), mute as i32, None, ptr::null_mut()) };
+            if !op.is_null() {
+                unsafe { pa_operation_unref(op) };
+            }
         }
     }
 
-    pub fn status(&mut self) -> Option<String> {
-        if self.context.is_null() {
-            let name_c = ffi::CString::new("dwmstatus").unwrap();
-            let context = unsafe { pa_context_new(self.mainloop_api, name_c.as_ptr()) };
-            if context.is_null() {
-                panic!("pa_context_new failed");
+    fn set_volume(&self, volume: &pa_cvolume) {
+        if let Some(name) = &self.default_sink_name {
+            let name_c = ffi::CString::new(&name[..]).unwrap();
+            let op = unsafe { pa_context_set_sink_volume_by_name(self.context, name_c.as_ptr(), volume, None, ptr::null_mut()) };
+            if !op.is_null() {
+                unsafe { pa_operation_unref(op) };
             }
-
-            let ret = unsafe{ pa_context_connect(context, ptr::null_mut(), PA_CONTEXT_NOAUTOSPAWN, ptr::null_mut()) };
-            if ret < 0 {
-                let err_str = unsafe { ffi::CStr::from_ptr(pa_strerror(ret)) }.to_str().unwrap();
-                panic!("pa_context_connect failed: {}", err_str);
-            }
-
-            self.context = context;
-            unsafe { pa_context_set_state_callback(self.context, Some(_state_cb), self as *mut _ as *mut c_void) };
-        }
-        loop {
-            let ret = unsafe { pa_mainloop_iterate(self.mainloop, 0, ptr::null_mut()) };
-            if ret < 0 {
-                let err_str = unsafe { ffi::CStr::from_ptr(pa_strerror(ret)) }.to_str().unwrap();
-                panic!("pa_mainloop_iterate failed: {}", err_str);
-            }
-            if ret == 0 {
-                break;
-            }
-        }
-
-        match self.level {
-            Some(level) => {
-                let mut result = "".to_string();
-                // Check for mute
-                if !self.mute {
-                    if level < 10 {
-                        result += VOL_UNMUTE;
-                    } else if level > 75 {
-                        result += VOL_HIGH;
-                    } else {
-                        result += VOL_LOW;
-                    }
-                } else {
-                    result += WARN;
-                    result += VOL_MUTE;
-                }
-                result += &format!(" {}%", level);
-                Some(result)
-            },
-            None => None,
         }
     }
 
@@ -167,8 +125,9 @@ impl Pulse {
             },
             PA_CONTEXT_FAILED => {
                 let errno = unsafe { pa_context_errno(self.context) };
-                let err_str = unsafe { ffi::CStr::from_ptr(pa_strerror(errno)) }.to_str().unwrap();
-                eprintln!("pa_context_get_state failed: {}", err_str);
+                let err_str = unsafe { ffi::CStr::from_ptr(pa_strerror(errno)) }.to_string_lossy();
+                self.error = Some(format!("pulseaudio: {}", err_str));
+                self.level = None;
                 unsafe { pa_context_unref(self.context) };
                 self.context = ptr::null_mut();
             },
@@ -177,36 +136,171 @@ impl Pulse {
 
     fn on_server_info_event(&mut self, i: &pa_server_info) {
         assert!(!self.context.is_null());
-        let sink_name = unsafe { ffi::CStr::from_ptr(i.default_sink_name) }.to_str().unwrap();
-        self.default_sink_name = Some(sink_name.to_string());
+        let sink_name = unsafe { ffi::CStr::from_ptr(i.default_sink_name) }.to_string_lossy();
+        self.default_sink_name = Some(sink_name.into_owned());
         unsafe { pa_context_get_sink_info_by_name(self.context, i.default_sink_name, Some(_sink_info_cb), self as *mut _ as *mut c_void) };
     }
 
     fn on_sink_info_event(&mut self, i: &pa_sink_info) {
         if self.default_sink_name.is_some() {
-            let sink_name = unsafe { ffi::CStr::from_ptr(i.name) }.to_str().unwrap();
-            if self.default_sink_name.as_ref().unwrap() == sink_name {
+            let sink_name = unsafe { ffi::CStr::from_ptr(i.name) }.to_string_lossy();
+            if *self.default_sink_name.as_ref().unwrap() == sink_name {
                 let volume = 100.0 * unsafe { pa_cvolume_avg(&i.volume as *const pa_cvolume) } as f64 / PA_VOLUME_NORM as f64;
                 self.level = Some(f64::round(volume) as u8);
+                self.volume = Some(i.volume);
                 self.mute = i.mute != 0;
             }
         }
     }
 
+    fn on_sink_info_error(&mut self) {
+        assert!(!self.context.is_null());
+        let errno = unsafe { pa_context_errno(self.context) };
+        let err_str = unsafe { ffi::CStr::from_ptr(pa_strerror(errno)) }.to_string_lossy();
+        self.error = Some(format!("pulseaudio: {}", err_str));
+    }
+
     fn on_subscribe_event(&mut self, t: pa_subscription_event_type_t, idx: u32) {
         assert!(!self.context.is_null());
         let facility = t & PA_SUBSCRIPTION_EVENT_FACILITY_MASK;
 
         match facility {
+            // a removed sink can't be looked up; if it was the default, the
+            // server's change comes next
+            PA_SUBSCRIPTION_EVENT_SINK if t & PA_SUBSCRIPTION_EVENT_TYPE_MASK == PA_SUBSCRIPTION_EVENT_REMOVE => (),
             PA_SUBSCRIPTION_EVENT_SINK => {
                 unsafe { pa_context_get_sink_info_by_index(self.context, idx, Some(_sink_info_cb), self as *mut _ as *mut c_void) };
             },
             PA_SUBSCRIPTION_EVENT_SERVER => {
                 unsafe { pa_context_get_server_info(self.context, Some(_server_info_cb), self as *mut _ as *mut c_void) };
             },
-            _ => {
-                panic!("Unexpected event: facility={}", t);
-            },
+            // not subscribed to, so nothing to do
+            _ => (),
+        };
+    }
+}
+
+impl Block for Pulse {
+    fn update(&mut self) -> Result<()> {
+        if self.context.is_null() {
+            let name_c = ffi::CString::new("dwmstatus").unwrap();
+            let context = unsafe { pa_context_new(self.mainloop.api(), name_c.as_ptr()) };
+            if context.is_null() {
+                return Err(Error::Lib("pa_context_new failed".to_string()));
+            }
+
+            let ret = unsafe{ pa_context_connect(context, ptr::null_mut(), PA_CONTEXT_NOAUTOSPAWN, ptr::null_mut()) };
+            if ret < 0 {
+                let errno = unsafe { pa_context_errno(context) };
+                let err_str = unsafe { ffi::CStr::from_ptr(pa_strerror(errno)) }.to_string_lossy().into_owned();
+                unsafe { pa_context_unref(context) };
+                return Err(Error::Lib(format!("pa_context_connect failed: {}", err_str)));
+            }
+
+            self.context = context;
+            unsafe { pa_context_set_state_callback(self.context, Some(_state_cb), self as *mut _ as *mut c_void) };
+        }
+        self.mainloop.dispatch();
+        match self.error.take() {
+            Some(err) => Err(Error::Lib(err)),
+            None => Ok(()),
+        }
+    }
+
+    fn event_fd(&self) -> Option<RawFd> {
+        Some(self.mainloop.fd())
+    }
+
+    // left: toggle mute, scroll: volume up/down
+    fn click(&mut self, button: u8) {
+        let action = match button {
+            1 => "toggle",
+            4 => "up",
+            5 => "down",
+            _ => { return; },
         };
+        let _ = self.action(action);
+    }
+
+    fn buttons(&self) -> &'static [u8] {
+        &[1, 4, 5]
+    }
+
+    fn action(&mut self, action: &str) -> Result<()> {
+        if self.context.is_null() || self.default_sink_name.is_none() {
+            return Err(Error::Lib("not connected".to_string()));
+        }
+        match (action, self.volume) {
+            ("mute", _) => self.set_mute(true),
+            ("unmute", _) => self.set_mute(false),
+            ("toggle", _) => self.set_mute(!self.mute),
+            ("up", Some(mut volume)) => {
+                unsafe { pa_cvolume_inc_clamp(&mut volume, VOL_STEP, PA_VOLUME_NORM) };
+                self.set_volume(&volume);
+            },
+            ("down", Some(mut volume)) => {
+                unsafe { pa_cvolume_dec(&mut volume, VOL_STEP) };
+                self.set_volume(&volume);
+            },
+            ("up", None) | ("down", None) => return Err(Error::Lib("no volume yet".to_string())),
+            _ => return Err(Error::NotFound(format!("no action {}", action))),
+        }
+        Ok(())
+    }
+
+    fn describe(&self) -> Option<String> {
+        self.default_sink_name.clone()
+    }
+
+    // keep the connection to the server
+    fn reconfigure(&mut self, config: &BlockConfig) -> bool {
+        self.format = config.get_format("format", FORMAT);
+        true
+    }
+
+    fn render(&self) -> Option<Text> {
+        match self.level {
+            Some(level) => {
+                let mut values = Values::new();
+                let mut severity = Severity::Normal;
+                // Check for mute
+                let icon = if !self.mute {
+                    if level < 10 {
+                        VOL_UNMUTE
+                    } else if level > 75 {
+                        VOL_HIGH
+                    } else {
+                        VOL_LOW
+                    }
+                } else {
+                    severity = Severity::Warn;
+                    values.insert("muted", "muted".to_string());
+                    VOL_MUTE
+                };
+                values.insert("icon", icon.to_string());
+                values.insert("volume", level.to_string());
+                if let Some(sink) = &self.default_sink_name {
+                    values.insert("sink", sink.clone());
+                }
+                Some(Text::new(severity, self.format.render(&values)))
+            },
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pulse;
+    use crate::block::Block;
+    use crate::config::BlockConfig;
+
+    #[test]
+    fn disconnect() {
+        // with a server running, this connects, and dropping disconnects
+        let mut pulse = Pulse::new(&BlockConfig::named("volume"));
+        pulse.init();
+        let _ = pulse.update();
+        drop(pulse);
     }
 }
@@ -0,0 +1,52 @@
+use std::path;
+
+// Where blocks look up the files they read (/proc, /sys, /etc/mtab): the
+// real root normally, a directory of captured files in tests.  System calls
+// (statvfs, getifaddrs, netlink) always see the running system.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Root(path::PathBuf);
+
+impl Default for Root {
+    fn default() -> Self {
+        Self(path::PathBuf::from("/"))
+    }
+}
+
+impl Root {
+    pub fn new(dir: impl Into<path::PathBuf>) -> Self {
+        Self(dir.into())
+    }
+
+    // The absolute `path`, eg. "/proc/meminfo", under this root
+    pub fn path(&self, path: &str) -> String {
+        self.0.join(path.trim_start_matches('/')).to_string_lossy().into_owned()
+    }
+}
+
+// One of the captured systems in tests/fixtures
+#[cfg(test)]
+pub fn fixture(name: &str) -> Root {
+    Root::new(path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name))
+}
+
+// A block `name` with `options` as they'd be written in its [[block]] table,
+// reading from `fixture` if given
+#[cfg(test)]
+pub fn config(name: &str, options: &str, fixture: Option<&str>) -> crate::config::BlockConfig {
+    let mut config: crate::config::BlockConfig = toml::from_str(&format!("name = {:?}\n{}", name, options)).unwrap();
+    if let Some(fixture) = fixture {
+        config.root = self::fixture(fixture);
+    }
+    config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Root;
+
+    #[test]
+    fn paths() {
+        assert_eq!(Root::default().path("/proc/meminfo"), "/proc/meminfo");
+        assert_eq!(Root::new("/tmp/laptop").path("/sys/class"), "/tmp/laptop/sys/class");
+    }
+}
@@ -0,0 +1,144 @@
+use crate::block::{Block, BUTTONS};
+use crate::config::BlockConfig;
+use crate::error::{Error, Result};
+use crate::format::{Format, Values};
+use crate::markup::{strip_controls, Severity, Text};
+use crate::prelude::*;
+use std::process;
+
+static FORMAT: &str = "{output}";
+static PREFIXES: &[(&str, Severity)] = &[("GOOD", Severity::Good), ("WARN", Severity::Warn), ("BAD", Severity::Bad)];
+
+// A user command, run every period (or on signal), showing the first line it
+// prints, like dwmblocks.  With `severity_prefix`, a leading "GOOD", "WARN"
+// or "BAD" (followed by a space or colon) selects the colour.
+pub struct Script {
+    command: String,
+    format: Format,
+    severity_prefix: bool,
+    // set by a click, for the next run
+    button: Option<u8>,
+    output: Option<Text>,
+}
+
+impl Script {
+    pub fn new(config: &BlockConfig) -> Self {
+        Self {
+            command: config.get_str("command", "").to_string(),
+            format: config.get_format("format", FORMAT),
+            severity_prefix: config.get_bool("severity_prefix", false),
+            button: None,
+            output: None,
+        }
+    }
+}
+
+// "WARN: 3 tickets" -> (Warn, "3 tickets")
+fn split_severity(line: &str) -> (Severity, &str) {
+    for (prefix, severity) in PREFIXES {
+        if let Some(rest) = line.strip_prefix(prefix) {
+            if rest.is_empty() || rest.starts_with([' ', ':']) {
+                return (*severity, rest.trim_start_matches(':').trim_start());
+            }
+        }
+    }
+    (Severity::Normal, line)
+}
+
+// A line of a command's output as the block's value, None if it's blank.
+// Shared with persistent commands.
+pub fn parse_line(line: &str, severity_prefix: bool) -> Option<Text> {
+    let line = strip_controls(line);
+    let line = line.trim_end();
+    if line.is_empty() {
+        None
+    } else if severity_prefix {
+        let (severity, text) = split_severity(line);
+        Some(Text::new(severity, text))
+    } else {
+        Some(Text::normal(line))
+    }
+}
+
+pub fn render_output(format: &Format, output: Option<&Text>) -> Option<Text> {
+    let output = output?;
+    let mut values = Values::new();
+    values.insert("output", output.text.clone());
+    Some(Text::new(output.severity, format.render(&values)))
+}
+
+impl Block for Script {
+    fn update(&mut self) -> Result<()> {
+        let mut command = shell(&self.command);
+        command.stdin(process::Stdio::null()).stderr(process::Stdio::inherit());
+        if let Some(button) = self.button.take() {
+            command.env("BLOCK_BUTTON", button.to_string());
+        }
+        let output = command.output().map_err(|err| Error::Io(self.command.clone(), err))?;
+        if !output.status.success() {
+            return Err(Error::Lib(format!("{}: {}", self.command, output.status)));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        self.output = parse_line(stdout.lines().next().unwrap_or(""), self.severity_prefix);
+        Ok(())
+    }
+
+    // run again right away, with $BLOCK_BUTTON set
+    fn click(&mut self, button: u8) {
+        self.button = Some(button);
+    }
+
+    fn buttons(&self) -> &'static [u8] {
+        BUTTONS
+    }
+
+    fn render(&self) -> Option<Text> {
+        render_output(&self.format, self.output.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_severity, Script};
+    use crate::block::Block;
+    use crate::markup::{Severity, Text};
+    use crate::root;
+
+    fn script(options: &str) -> Script {
+        Script::new(&root::config("script", options, None))
+    }
+
+    #[test]
+    fn output() {
+        let mut first = script("command = \"printf 'one\\\\ntwo\\\\n'\"");
+        first.update().unwrap();
+        assert_eq!(first.render(), Some(Text::normal("one")));
+        let mut empty = script("command = \"true\"");
+        empty.update().unwrap();
+        assert_eq!(empty.render(), None);
+        let mut controls = script("command = \"printf 'a\\\\000b\\\\002c\\\\n'\"");
+        controls.update().unwrap();
+        assert_eq!(controls.render(), Some(Text::normal("abc")));
+        assert!(script("command = \"echo oops; exit 3\"").update().is_err());
+        let mut clicked = script("command = \"echo button $BLOCK_BUTTON\"\nformat = \"<{output}>\"");
+        clicked.click(3);
+        clicked.update().unwrap();
+        assert_eq!(clicked.render(), Some(Text::normal("<button 3>")));
+        clicked.update().unwrap();
+        assert_eq!(clicked.render(), Some(Text::normal("<button>")));
+    }
+
+    #[test]
+    fn severities() {
+        assert_eq!(split_severity("WARN: 3 tickets"), (Severity::Warn, "3 tickets"));
+        assert_eq!(split_severity("BAD on call"), (Severity::Bad, "on call"));
+        assert_eq!(split_severity("GOOD"), (Severity::Good, ""));
+        assert_eq!(split_severity("GOODBYE"), (Severity::Normal, "GOODBYE"));
+        let mut prefixed = script("command = \"echo 'BAD: down'\"\nseverity_prefix = true");
+        prefixed.update().unwrap();
+        assert_eq!(prefixed.render(), Some(Text::new(Severity::Bad, "down")));
+        let mut plain = script("command = \"echo 'BAD: down'\"");
+        plain.update().unwrap();
+        assert_eq!(plain.render(), Some(Text::normal("BAD: down")));
+    }
+}
@@ -1,28 +1,65 @@
-use crate::prelude::*;
+use crate::block::Block;
+use crate::config::BlockConfig;
+use crate::error::{self, Error, Result};
+use crate::format::{Format, Values};
+use crate::markup::{Severity, Text};
+use crate::root::Root;
 use std::io::prelude::*;
 use std::{fs, io};
-use std::str::FromStr;
 use glob::glob;
 
 const ICON: &str = "🌡";
+static ZONE: &str = "x86_pkg_temp";
+static FORMAT: &str = "{icon}{temp}°C";
+static BAD_TEMP: f64 = 75.0;
 
 pub struct Temp {
+    root: Root,
+    format: Format,
+    zone: String,
+    bad: i32,
     path: Option<String>,
+    temp: Option<i32>,
 }
 
 impl Temp {
-    pub fn new() -> Self {
+    pub fn new(config: &BlockConfig) -> Self {
+        Self {
+            root: config.root.clone(),
+            format: config.get_format("format", FORMAT),
+            zone: config.get_str("zone", ZONE).to_string(),
+            bad: config.get_f64("bad", BAD_TEMP) as i32,
+            path: None,
+            temp: None,
+        }
+    }
+}
+
+impl Block for Temp {
+    fn init(&mut self) {
         let mut result = None;
-        for entry in glob("/sys/class/thermal/thermal_zone*/type").expect("Failed to read glob pattern") {
+        // the root may be any directory, eg. one with a "[" in its name
+        let pattern = format!("{}/thermal_zone*/type", glob::Pattern::escape(&self.root.path("/sys/class/thermal")));
+        let entries = match glob(&pattern) {
+            Ok(entries) => entries,
+            Err(err) => {
+                eprintln!("dwmstatus: {}: {}", pattern, err);
+                return;
+            },
+        };
+        for entry in entries {
             match entry {
                 Ok(path) => {
-                    let file = fs::File::open(&path).expect("Failed to open thermal type path");
-                    for line in io::BufReader::new(file).lines() {
-                        if line.expect("Failed to read line") == "x86_pkg_temp" {
-                            let parent = path.parent().expect("Failed to get thermal type parent");
-                            let temp = parent.join("temp");
-                            let temp_str = temp.to_str().expect("Failed to join thermal temp path");
-                            result = Some(temp_str.to_string());
+                    // skip zones we can't read
+                    let file = match fs::File::open(&path) {
+                        Ok(file) => file,
+                        Err(_err) => continue,
+                    };
+                    for line in io::BufReader::new(file).lines().map_while(|line| line.ok()) {
+                        if line == self.zone {
+                            if let Some(temp) = path.parent().and_then(|parent| parent.join("temp").to_str().map(|temp| temp.to_string())) {
+                                result = Some(temp);
+                            }
                             break;
                         }
                     }
@@ -33,30 +70,75 @@ impl Temp {
                 Err(_err) => (),
             }
         }
-        Temp {
-            path: result
-        }
+        self.path = result;
     }
 
-    pub fn status(&self) -> String {
+    fn update(&mut self) -> Result<()> {
+        if self.path.is_none() {
+            // the zone may show up later, eg. once its module is loaded
+            self.init();
+        }
         match &self.path {
             Some(path) => {
-                let data = fs::read_to_string(path).expect("Failed to open thermal temp path");
-                let temp = i32::from_str(&data.trim_end()).expect("Failed to parse thermal temp") / 1000;
+                let data = fs::read_to_string(path).map_err(error::io(path))?;
+                self.temp = Some(error::parse::<i32>(path, &data)? / 1000);
+                Ok(())
+            },
+            None => Err(Error::NotFound(format!("no thermal zone of type {}", self.zone))),
+        }
+    }
 
-                let mut ret = if temp > 75 {
-                    BAD.to_string()
+    // without a path, update() says why
+    fn describe(&self) -> Option<String> {
+        self.path.as_ref().map(|path| format!("{} ({})", path, self.zone))
+    }
+
+    fn render(&self) -> Option<Text> {
+        match self.temp {
+            Some(temp) => {
+                let severity = if temp > self.bad {
+                    Severity::Bad
                 } else {
-                    "".to_string()
+                    Severity::Normal
                 };
-                ret += &format!("{}{}°C", ICON, temp);
-                ret
+                let mut values = Values::new();
+                values.insert("icon", ICON.to_string());
+                values.insert("temp", temp.to_string());
+                Some(Text::new(severity, self.format.render(&values)))
             },
-            None => {
-                let mut ret = BAD.to_string();
-                ret += &format!("{}err", ICON);
-                ret
-            }
+            None => Some(Text::new(Severity::Bad, format!("{}err", ICON))),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Temp;
+    use crate::block::Block;
+    use crate::error::Error;
+    use crate::markup::{Severity, Text};
+    use crate::root;
+
+    fn temp(fixture: &str, options: &str) -> Temp {
+        let config = root::config("temp", options, Some(fixture));
+        let mut temp = Temp::new(&config);
+        temp.init();
+        temp
+    }
+
+    #[test]
+    fn zones() {
+        let mut intel = temp("intel-discharging", "");
+        intel.update().unwrap();
+        assert_eq!(intel.render(), Some(Text::new(Severity::Normal, "🌡52°C")));
+        assert!(intel.describe().unwrap().ends_with("thermal_zone2/temp (x86_pkg_temp)"));
+        let mut hot = temp("intel-discharging", "bad = 50");
+        hot.update().unwrap();
+        assert_eq!(hot.render().unwrap().severity, Severity::Bad);
+        // no x86_pkg_temp on AMD, the ACPI zone has to be picked
+        assert!(matches!(temp("amd-desktop", "").update(), Err(Error::NotFound(_))));
+        let mut amd = temp("amd-desktop", "zone = \"acpitz\"");
+        amd.update().unwrap();
+        assert_eq!(amd.render(), Some(Text::new(Severity::Normal, "🌡47°C")));
+    }
+}
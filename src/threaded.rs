@@ -0,0 +1,342 @@
+use crate::block::{self, Block};
+use crate::config::BlockConfig;
+use crate::error::{Error, Result};
+use crate::markup::Text;
+use crate::mainloop::arm_timer;
+use libc::{epoll_create1, epoll_ctl, timerfd_create};
+use std::os::unix::io::RawFd;
+use std::sync::{mpsc, Arc};
+use std::{mem, thread, time};
+
+static STALE: &str = "⌛";
+
+enum Request {
+    Update,
+    Click(u8),
+    Action(String),
+    Resume,
+    // answered with whether the block took it
+    Reconfigure(BlockConfig, mpsc::Sender<bool>),
+    Describe(mpsc::Sender<Option<String>>),
+}
+
+// The file descriptors shared with the worker, closed once both sides are done
+// with them.  `epfd` is what the main loop watches: it holds `timer`, which
+// wakes us when the worker is done or has timed out, and the block's own
+// event_fd, if it has one.
+struct Fds {
+    epfd: RawFd,
+    timer: RawFd,
+    block: Option<RawFd>,
+}
+
+impl Fds {
+    fn new() -> Result<Self> {
+        let epfd = os_check!(epoll_create1(libc::EPOLL_CLOEXEC))?;
+        let timer = match os_check!(timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_NONBLOCK | libc::TFD_CLOEXEC)) {
+            Ok(timer) => timer,
+            Err(err) => {
+                unsafe { libc::close(epfd) };
+                return Err(err);
+            },
+        };
+        let result = Self { epfd, timer, block: None };
+        result.ctl(libc::EPOLL_CTL_ADD, timer, libc::EPOLLIN as u32)?;
+        Ok(result)
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, events: u32) -> Result<()> {
+        let mut event = libc::epoll_event { events, u64: fd as u64 };
+        let epfd = self.epfd;
+        os_check!(epoll_ctl(epfd, op, fd, &mut event))?;
+        Ok(())
+    }
+
+    // The block's event_fd fires once, until the worker has handled it
+    fn watch_block(&mut self, fd: RawFd) -> Result<()> {
+        self.ctl(libc::EPOLL_CTL_ADD, fd, (libc::EPOLLIN | libc::EPOLLONESHOT) as u32)?;
+        self.block = Some(fd);
+        Ok(())
+    }
+
+    fn rearm_block(&self) {
+        if let Some(fd) = self.block {
+            let _ = self.ctl(libc::EPOLL_CTL_MOD, fd, (libc::EPOLLIN | libc::EPOLLONESHOT) as u32);
+        }
+    }
+
+    fn arm(&self, timeout: time::Duration) {
+        let _ = arm_timer(self.timer, timeout);
+    }
+
+    // Consume whatever made `epfd` readable, so the main loop doesn't see it
+    // again: the timer's expirations, and the block's one-shot event
+    fn drain(&self) {
+        let mut events: [libc::epoll_event; 2] = unsafe { mem::zeroed() };
+        unsafe { libc::epoll_wait(self.epfd, events.as_mut_ptr(), events.len() as libc::c_int, 0) };
+        let mut expirations = 0u64;
+        unsafe { libc::read(self.timer, &mut expirations as *mut _ as *mut libc::c_void, mem::size_of::<u64>()) };
+    }
+}
+
+impl Drop for Fds {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.timer) };
+        unsafe { libc::close(self.epfd) };
+    }
+}
+
+// Runs a block that may block (eg. on D-Bus or netlink) on a worker thread,
+// so it can't hold up the others.  While an update takes longer than
+// `timeout`, the last value is shown, marked as stale.
+pub struct Threaded {
+    block: Option<Box<dyn Block + Send>>,
+    timeout: time::Duration,
+    fds: Option<Arc<Fds>>,
+    requests: Option<mpsc::Sender<Request>>,
+    results: Option<mpsc::Receiver<Result<Option<Text>>>>,
+    // when the outstanding update was requested
+    pending: Option<time::Instant>,
+    value: Option<Text>,
+    stale: bool,
+    // the block's, which is out of reach once it's on the worker
+    buttons: &'static [u8],
+}
+
+impl Threaded {
+    pub fn new(block: Box<dyn Block + Send>, timeout: time::Duration) -> Self {
+        let buttons = block.buttons();
+        Self { block: Some(block), timeout, fds: None, requests: None, results: None, pending: None, value: None, stale: false, buttons }
+    }
+
+    // How long to wait for the worker to answer: what's left of the timeout
+    // of the update it's on, if any.  None once that update is stale, as the
+    // worker may never get to us.
+    fn patience(&self) -> Option<time::Duration> {
+        let since = self.pending.unwrap_or_else(time::Instant::now);
+        (since + self.timeout).checked_duration_since(time::Instant::now())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        let mut block = self.block.take().unwrap();
+        block.init();
+        let mut fds = Fds::new()?;
+        if let Some(fd) = block.event_fd() {
+            fds.watch_block(fd)?;
+        }
+        let fds = Arc::new(fds);
+        let (requests, worker_requests) = mpsc::channel();
+        let (worker_results, results) = mpsc::channel();
+        let worker_fds = fds.clone();
+        thread::Builder::new().name("dwmstatus-worker".to_string()).spawn(move || {
+            for request in worker_requests {
+                match request {
+                    Request::Update => {
+                        let result = block.update().map(|()| block.render());
+                        if worker_results.send(result).is_err() {
+                            break;
+                        }
+                        worker_fds.rearm_block();
+                        worker_fds.arm(time::Duration::from_nanos(1));
+                    },
+                    Request::Click(button) => block.click(button),
+                    Request::Resume => block.resume(),
+                    Request::Action(action) => {
+                        if let Err(err) = block.action(&action) {
+                            eprintln!("dwmstatus: {}: {}", action, err);
+                        }
+                    },
+                    Request::Reconfigure(config, reply) => {
+                        let _ = reply.send(block.reconfigure(&config));
+                    },
+                    Request::Describe(reply) => {
+                        let _ = reply.send(block.describe());
+                    },
+                }
+            }
+        }).map_err(|err| Error::Os("pthread_create", err))?;
+        self.fds = Some(fds);
+        self.requests = Some(requests);
+        self.results = Some(results);
+        Ok(())
+    }
+}
+
+impl Block for Threaded {
+    fn init(&mut self) {
+        if let Err(err) = self.start() {
+            // update() reports it
+            eprintln!("dwmstatus: worker: {}", err);
+        }
+    }
+
+    fn update(&mut self) -> Result<()> {
+        let (fds, requests, results) = match (&self.fds, &self.requests, &self.results) {
+            (Some(fds), Some(requests), Some(results)) => (fds, requests, results),
+            _ => return Err(Error::Lib("worker thread failed to start".to_string())),
+        };
+        fds.drain();
+        let now = time::Instant::now();
+        match results.try_recv() {
+            Ok(result) => {
+                self.pending = None;
+                self.stale = false;
+                self.value = result?;
+                return Ok(());
+            },
+            Err(mpsc::TryRecvError::Empty) => (),
+            Err(mpsc::TryRecvError::Disconnected) => return Err(Error::Lib("worker thread exited".to_string())),
+        }
+        match self.pending {
+            Some(since) => {
+                if now - since >= self.timeout {
+                    self.stale = true;
+                }
+            },
+            None => {
+                // arm before asking, so the worker's wakeup can't be overwritten
+                fds.arm(self.timeout);
+                if requests.send(Request::Update).is_err() {
+                    return Err(Error::Lib("worker thread exited".to_string()));
+                }
+                self.pending = Some(now);
+            },
+        }
+        Ok(())
+    }
+
+    fn render(&self) -> Option<Text> {
+        let value = self.value.as_ref()?;
+        if self.stale {
+            Some(Text::new(value.severity, value.text.clone() + " " + STALE))
+        } else {
+            Some(value.clone())
+        }
+    }
+
+    fn event_fd(&self) -> Option<RawFd> {
+        self.fds.as_ref().map(|fds| fds.epfd)
+    }
+
+    fn click(&mut self, button: u8) {
+        if let Some(requests) = &self.requests {
+            let _ = requests.send(Request::Click(button));
+        }
+    }
+
+    fn buttons(&self) -> &'static [u8] {
+        self.buttons
+    }
+
+    // before the update that follows
+    fn resume(&mut self) {
+        if let Some(requests) = &self.requests {
+            let _ = requests.send(Request::Resume);
+        }
+    }
+
+    // waits for the worker, unless it's stuck; then the block is replaced
+    fn reconfigure(&mut self, config: &BlockConfig) -> bool {
+        let (requests, patience) = match (&self.requests, self.patience()) {
+            (Some(requests), Some(patience)) => (requests, patience),
+            _ => { return false; },
+        };
+        let (reply, result) = mpsc::channel();
+        if requests.send(Request::Reconfigure(config.clone(), reply)).is_err() {
+            return false;
+        }
+        match result.recv_timeout(patience) {
+            Ok(true) => {
+                self.timeout = block::timeout(config);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // after any update that's underway, unless it's stuck
+    fn describe(&self) -> Option<String> {
+        let patience = self.patience()?;
+        let (reply, result) = mpsc::channel();
+        self.requests.as_ref()?.send(Request::Describe(reply)).ok()?;
+        result.recv_timeout(patience).ok()?
+    }
+
+    // runs in the background, so errors are only logged
+    fn action(&mut self, action: &str) -> Result<()> {
+        match &self.requests {
+            Some(requests) => requests.send(Request::Action(action.to_string())).map_err(|_| Error::Lib("worker thread exited".to_string())),
+            None => Err(Error::Lib("worker thread failed to start".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Threaded;
+    use crate::block::Block;
+    use crate::error::Result;
+    use crate::markup::Text;
+    use crate::root;
+    use std::{thread, time};
+
+    // takes `delay` per update, counting them
+    struct Slow {
+        delay: time::Duration,
+        count: u32,
+    }
+
+    impl Block for Slow {
+        fn update(&mut self) -> Result<()> {
+            thread::sleep(self.delay);
+            self.count += 1;
+            Ok(())
+        }
+
+        fn render(&self) -> Option<Text> {
+            Some(Text::normal(self.count.to_string()))
+        }
+    }
+
+    fn wait(block: &Threaded) {
+        let mut fd = libc::pollfd { fd: block.event_fd().unwrap(), events: libc::POLLIN, revents: 0 };
+        assert_eq!(unsafe { libc::poll(&mut fd, 1, 5000) }, 1);
+    }
+
+    #[test]
+    fn stale() {
+        let slow = Slow { delay: time::Duration::from_millis(100), count: 0 };
+        let mut block = Threaded::new(Box::new(slow), time::Duration::from_millis(20));
+        block.init();
+        block.update().unwrap();
+        assert_eq!(block.render(), None);
+        // the timeout, then the result
+        wait(&block);
+        block.update().unwrap();
+        wait(&block);
+        block.update().unwrap();
+        assert_eq!(block.render(), Some(Text::normal("1")));
+        block.update().unwrap();
+        wait(&block);
+        block.update().unwrap();
+        assert_eq!(block.render(), Some(Text::normal("1 ⌛")));
+        wait(&block);
+        block.update().unwrap();
+        assert_eq!(block.render(), Some(Text::normal("2")));
+    }
+
+    #[test]
+    fn stuck() {
+        let slow = Slow { delay: time::Duration::from_millis(500), count: 0 };
+        let mut block = Threaded::new(Box::new(slow), time::Duration::from_millis(20));
+        block.init();
+        block.update().unwrap();
+        wait(&block);
+        block.update().unwrap();
+        // past the timeout, so neither waits for the worker
+        let start = time::Instant::now();
+        assert_eq!(block.describe(), None);
+        assert!(!block.reconfigure(&root::config("slow", "", None)));
+        assert!(start.elapsed() < time::Duration::from_millis(100));
+    }
+}
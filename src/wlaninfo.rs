@@ -1,5 +1,6 @@
 use libc::{c_int, c_void, if_nametoindex, ETH_ALEN};
 use std::{ffi, mem, ptr, slice};
+use crate::error::{Error, Result};
 
 pub struct WLanInfo {
     pub essid: Option<String>,
@@ -639,43 +640,46 @@ struct NlSocket {
 }
 
 impl NlSocket {
-    fn new() -> Self {
+    fn new() -> Result<Self> {
         let result = unsafe { nl_socket_alloc() };
         if result.is_null() {
-            panic!("nl_socket_alloc failed");
+            return Err(Error::Lib("nl_socket_alloc failed".to_string()));
         }
-        Self { ptr: result }
+        Ok(Self { ptr: result })
     }
 
-    fn connect(&self) {
+    fn connect(&self) -> Result<()> {
         let ret = unsafe { genl_connect(self.ptr) };
         if ret < 0 {
-            panic!("genl_connect returned {}", ret);
+            return Err(Error::Lib(format!("genl_connect returned {}", ret)));
         }
+        Ok(())
     }
 
-    fn modify_cb(&self, type_: nl_cb_type, kind: nl_cb_kind, func: extern fn(*mut nl_msg, arg: *mut WLanInfo) -> c_int, arg: *mut WLanInfo) {
+    fn modify_cb(&self, type_: nl_cb_type, kind: nl_cb_kind, func: extern fn(*mut nl_msg, arg: *mut WLanInfo) -> c_int, arg: *mut WLanInfo) -> Result<()> {
         let ret = unsafe { nl_socket_modify_cb(self.ptr, type_, kind, func, arg) };
         if ret < 0 {
-            panic!("nl_socket_modify_cb returned {}", ret);
+            return Err(Error::Lib(format!("nl_socket_modify_cb returned {}", ret)));
         }
+        Ok(())
     }
 
-    fn send_sync(&self, msg: NlMsg) {
+    fn send_sync(&self, msg: NlMsg) -> Result<()> {
         let ret = unsafe { nl_send_sync(self.ptr, msg.ptr) };
         mem::forget(msg); // nl_send_sync frees the msg internally
         if ret < 0 {
-            panic!("nl_send_sync returned {}", ret);
+            return Err(Error::Lib(format!("nl_send_sync returned {}", ret)));
         }
+        Ok(())
     }
 
-    fn ctrl_resolve(&self, name: &str) -> c_int {
+    fn ctrl_resolve(&self, name: &str) -> Result<c_int> {
         let name_c = ffi::CString::new(name).unwrap();
         let ret = unsafe { genl_ctrl_resolve(self.ptr, name_c.as_ptr()) };
         if ret < 0 {
-            panic!("genl_ctrl_resolve returned {}", ret);
+            return Err(Error::Lib(format!("genl_ctrl_resolve returned {}", ret)));
         }
-        ret
+        Ok(ret)
     }
 }
 
@@ -690,33 +694,36 @@ struct NlMsg {
 }
 
 impl NlMsg {
-    fn new() -> Self {
+    fn new() -> Result<Self> {
         let result = unsafe { nlmsg_alloc() };
         if result.is_null() {
-            panic!("nlmsg_alloc failed");
+            return Err(Error::Lib("nlmsg_alloc failed".to_string()));
         }
-        Self { ptr: result }
+        Ok(Self { ptr: result })
     }
 
-    fn genl_put(&self, port: u32, seq: u32, family: c_int, hdrlen: c_int, flags: c_int, cmd: u8, version: u8) {
+    fn genl_put(&self, port: u32, seq: u32, family: c_int, hdrlen: c_int, flags: c_int, cmd: u8, version: u8) -> Result<()> {
         let ret = unsafe { genlmsg_put(self.ptr, port, seq, family, hdrlen, flags, cmd, version) };
         if ret.is_null() {
-            panic!("genlmsg_put returned NULL");
+            return Err(Error::Lib("genlmsg_put returned NULL".to_string()));
         }
+        Ok(())
     }
 
-    fn attr_put(&self, attrtype: c_int, datalen: c_int, data: *const c_void) {
+    fn attr_put(&self, attrtype: c_int, datalen: c_int, data: *const c_void) -> Result<()> {
         let ret = unsafe { nla_put(self.ptr, attrtype, datalen, data) };
         if ret < 0 {
-            panic!("nla_put returned {}", ret);
+            return Err(Error::Lib(format!("nla_put returned {}", ret)));
         }
+        Ok(())
     }
 
-    fn attr_put_u32(&self, attrtype: c_int, value: u32) {
+    fn attr_put_u32(&self, attrtype: c_int, value: u32) -> Result<()> {
         let ret = unsafe { nla_put_u32(self.ptr, attrtype, value) };
         if ret < 0 {
-            panic!("nla_put_u32 returned {}", ret);
+            return Err(Error::Lib(format!("nla_put_u32 returned {}", ret)));
         }
+        Ok(())
     }
 }
 
@@ -820,35 +827,36 @@ extern "C" fn gwi_scan_cb(msg: *mut nl_msg, data: *mut WLanInfo) -> c_int {
 }
 
 impl WLanInfo {
-    pub fn new(interface: &str) -> Self {
+    pub fn new(interface: &str) -> Result<Self> {
         let mut result = Self { essid: None, bssid: [0; ETH_ALEN as usize], quality: None, bitrate: None, frequency: None };
 
-        let sk = NlSocket::new();
-        sk.connect();
+        let sk = NlSocket::new()?;
+        sk.connect()?;
 
-        sk.modify_cb(nl_cb_type::NL_CB_VALID, nl_cb_kind::NL_CB_CUSTOM, gwi_scan_cb, &mut result);
-        let nl80211_id = sk.ctrl_resolve("nl80211");
+        sk.modify_cb(nl_cb_type::NL_CB_VALID, nl_cb_kind::NL_CB_CUSTOM, gwi_scan_cb, &mut result)?;
+        let nl80211_id = sk.ctrl_resolve("nl80211")?;
 
         let iface_c = ffi::CString::new(interface).unwrap();
         let ifidx = unsafe { if_nametoindex(iface_c.as_ptr()) };
         if ifidx == 0 {
-            panic!("if_nametoindex returned 0");
+            // the interface went away since getifaddrs()
+            return Err(Error::NotFound(format!("no interface {}", interface)));
         }
 
-        let mut msg = NlMsg::new();
-        msg.genl_put(NL_AUTO_PORT, NL_AUTO_SEQ, nl80211_id, 0, NLM_F_DUMP, nl80211_commands::NL80211_CMD_GET_SCAN as u8, 0);
-        msg.attr_put_u32(nl80211_attr::NL80211_ATTR_IFINDEX as i32, ifidx);
-        sk.send_sync(msg);
+        let mut msg = NlMsg::new()?;
+        msg.genl_put(NL_AUTO_PORT, NL_AUTO_SEQ, nl80211_id, 0, NLM_F_DUMP, nl80211_commands::NL80211_CMD_GET_SCAN as u8, 0)?;
+        msg.attr_put_u32(nl80211_attr::NL80211_ATTR_IFINDEX as i32, ifidx)?;
+        sk.send_sync(msg)?;
 
-        sk.modify_cb(nl_cb_type::NL_CB_VALID, nl_cb_kind::NL_CB_CUSTOM, gwi_sta_cb, &mut result);
+        sk.modify_cb(nl_cb_type::NL_CB_VALID, nl_cb_kind::NL_CB_CUSTOM, gwi_sta_cb, &mut result)?;
 
-        msg = NlMsg::new();
-        msg.genl_put(NL_AUTO_PORT, NL_AUTO_SEQ, nl80211_id, 0, NLM_F_DUMP, nl80211_commands::NL80211_CMD_GET_STATION as u8, 0);
-        msg.attr_put_u32(nl80211_attr::NL80211_ATTR_IFINDEX as i32, ifidx);
-        msg.attr_put(nl80211_attr::NL80211_ATTR_MAC as i32, result.bssid.len() as i32, &result.bssid as *const _ as *const c_void);
-        sk.send_sync(msg);
+        msg = NlMsg::new()?;
+        msg.genl_put(NL_AUTO_PORT, NL_AUTO_SEQ, nl80211_id, 0, NLM_F_DUMP, nl80211_commands::NL80211_CMD_GET_STATION as u8, 0)?;
+        msg.attr_put_u32(nl80211_attr::NL80211_ATTR_IFINDEX as i32, ifidx)?;
+        msg.attr_put(nl80211_attr::NL80211_ATTR_MAC as i32, result.bssid.len() as i32, &result.bssid as *const _ as *const c_void)?;
+        sk.send_sync(msg)?;
 
-        result
+        Ok(result)
     }
 
     fn handle_scan_cb(&mut self, data: NlMsgHdrData) -> nl_cb_action {